@@ -1,4 +1,8 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use crate::generics::layout::{GENESET_LAYOUT, GROUP_FILE_NAME, SPLIT_LAYOUT};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+// Most files open at once when every chunk or group is filled together
+pub const MAX_OPEN: usize = 64;
 
 const TUTILS_LOGO: &str = "
 ==========================================================
@@ -21,6 +25,66 @@ pub enum DType {
     Other,
 }
 
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum SplitStrategy {
+    Stream,
+    FirstFitDecreasing,
+    EqualBins,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum NFilesMode {
+    Contiguous,
+    RoundRobin,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ManifestType {
+    Tsv,
+    Json,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum RestoreFormat {
+    Fasta,
+    Bed,
+    Paf,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CollisionPolicy {
+    Fail,
+    Suffix,
+    FirstToken,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum HeaderFormat {
+    Template,
+    KeyValue,
+    Gff,
+    Blast,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum DedupeKeep {
+    First,
+    SmallestId,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum MismatchPolicy {
+    Warn,
+    Fail,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum ExistingFiles {
+    Fail,
+    Overwrite,
+    Skip,
+}
+
 #[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum OType {
     File,
@@ -35,7 +99,461 @@ pub enum OriginType {
     Ncbi,
 }
 
+/// Options shared by every split command
+#[derive(Args, Debug, Clone)]
+pub struct SplitOptions {
+    // Path to the TreeVal yaml file generated by the user
+    #[arg(short = 'f', long, help = "Path to the input FASTA file")]
+    pub fasta_file: String,
+
+    #[arg(
+        short = 'd',
+        long = "data_type",
+        value_enum,
+        help = "Data type of the input fasta file, detected if not given"
+    )]
+    pub data_type: Option<DType>,
+
+    #[arg(
+        short = 'r',
+        long = "origin_db",
+        value_enum,
+        help = "Database the input fasta file came from, detected if not given"
+    )]
+    pub origin_db: Option<OriginType>,
+
+    #[arg(
+        long = "accession",
+        alias = "name",
+        help = "Name the output is filed under, {accession} in --layout, worked out from the input file name if not given"
+    )]
+    pub accession: Option<String>,
+
+    #[arg(
+        long = "label",
+        help = "Name of the output folder for -d other, e.g. ncrna, transposons, domains"
+    )]
+    pub label: Option<String>,
+
+    #[arg(
+        long = "layout",
+        default_value = SPLIT_LAYOUT,
+        help = "Folders under -o the chunks go in, from {clade}, {organism}, {accession} and {type}"
+    )]
+    pub layout: String,
+
+    #[arg(
+        long = "file-name",
+        help = "Name of each chunk, from the --layout placeholders plus {index}, {count} (records in the chunk) and {size} (-s), default {accession}_f{index}_c{size}.fa for splitcount and {accession}_f{index}_{type}.fasta for sizesplit"
+    )]
+    pub file_name: Option<String>,
+
+    #[arg(long = "clade", help = "Fills in {clade} in --layout and --file-name")]
+    pub clade: Option<String>,
+
+    #[arg(
+        long = "organism",
+        help = "Fills in {organism} in --layout and --file-name"
+    )]
+    pub organism: Option<String>,
+
+    #[arg(
+        long = "detect-records",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(usize),
+        help = "Number of records sampled to detect or check -d and -r, 0 turns this off"
+    )]
+    pub detect_records: usize,
+
+    #[arg(
+        long = "on-mismatch",
+        value_enum,
+        default_value_t = MismatchPolicy::Warn,
+        help = "Whether -d or -r contradicting the detected values warns or stops the run"
+    )]
+    pub on_mismatch: MismatchPolicy,
+
+    #[arg(
+        short = 'c',
+        long = "clean_headers",
+        value_parser = clap::value_parser!(bool),
+        help = "Choose whether to clean the headers"
+    )]
+    pub sanitise: bool,
+
+    #[arg(
+        long = "rules",
+        help = "YAML or TOML file of extra header dialects for --clean_headers"
+    )]
+    pub rules: Option<String>,
+
+    #[arg(
+        long = "dialect",
+        help = "Header dialect used by --clean_headers, defaults to the one named after --origin_db"
+    )]
+    pub dialect: Option<String>,
+
+    #[arg(
+        long = "on-collision",
+        value_enum,
+        default_value_t = CollisionPolicy::Suffix,
+        help = "What to do when a sanitised header is empty or already used",
+        long_help = "fail: stop the run\nsuffix: add _2, _3... to a duplicate, an empty header falls back to the original first token\nfirst-token: use the original first token instead"
+    )]
+    pub on_collision: CollisionPolicy,
+
+    #[arg(
+        long = "header-format",
+        value_enum,
+        default_value_t = HeaderFormat::Template,
+        help = "How --clean_headers writes the fields it finds",
+        long_help = "template: the dialect's own template\nkey-value: protein_id=...;gene_id=...;transcript_id=...;biotype=...;source=...;gene_name=...\ngff: GFF3 column 9 attributes, ID=...;gene_id=...;Name=...\nblast: one token, every field joined by __ and empty if missing"
+    )]
+    pub header_format: HeaderFormat,
+
+    #[arg(
+        long = "annotation",
+        requires = "sanitise",
+        help = "GFF3 or GTF for the input, records are joined to it by ID to fill in the fields --clean_headers writes"
+    )]
+    pub annotation: Option<String>,
+
+    #[arg(
+        long = "with-location",
+        requires = "annotation",
+        help = "Add the genomic location from --annotation to the sanitised headers"
+    )]
+    pub with_location: bool,
+
+    #[arg(
+        short = 'o',
+        long = "output-directory",
+        default_value = "./",
+        help = "The output directory that files will be placed in"
+    )]
+    pub outpath: String,
+
+    #[arg(
+        long = "manifest-format",
+        value_enum,
+        default_value_t = ManifestType::Tsv,
+        help = "Format of the manifest describing each output file"
+    )]
+    pub manifest_format: ManifestType,
+
+    #[arg(
+        long = "force",
+        conflicts_with = "skip_existing",
+        help = "Overwrite output files that already exist"
+    )]
+    pub force: bool,
+
+    #[arg(
+        long = "skip-existing",
+        help = "Keep output files that already exist rather than writing them again"
+    )]
+    pub skip_existing: bool,
+
+    #[arg(
+        short = 't',
+        long = "threads",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(usize),
+        help = "Number of threads sanitising headers and writing files"
+    )]
+    pub threads: usize,
+
+    #[arg(
+        long = "in-flight",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(usize),
+        help = "Most chunks held in memory waiting to be written"
+    )]
+    pub in_flight: usize,
+
+    #[arg(
+        long = "max-open",
+        default_value_t = MAX_OPEN,
+        value_parser = clap::value_parser!(usize),
+        help = "Most chunk files open at once when every file is filled together"
+    )]
+    pub max_open: usize,
+
+    #[arg(
+        long = "longest-isoform",
+        help = "Keep one record per gene, the gene_id of the header dialect, the canonical/MANE tagged one if marked, otherwise the longest"
+    )]
+    pub longest_isoform: bool,
+
+    #[arg(
+        long = "min-length",
+        help = "Drop records with fewer residues than this"
+    )]
+    pub min_length: Option<usize>,
+
+    #[arg(
+        long = "max-length",
+        help = "Drop records with more residues than this"
+    )]
+    pub max_length: Option<usize>,
+
+    #[arg(
+        long = "max-ambiguous",
+        help = "Drop records where more than this fraction of residues are ambiguous (X/B/Z/J for pep, anything but ACGTU otherwise)"
+    )]
+    pub max_ambiguous: Option<f64>,
+
+    #[arg(
+        long = "drop-internal-stops",
+        help = "Drop peptides with a * before the end"
+    )]
+    pub drop_internal_stops: bool,
+
+    #[arg(
+        long = "check-cds",
+        help = "Drop CDS that aren't whole codons from ATG to a stop, with no stop in between"
+    )]
+    pub check_cds: bool,
+
+    #[arg(
+        long = "check-alphabet",
+        help = "Drop records that look like nucleotide in a pep file, or amino acid in a nucleotide one"
+    )]
+    pub check_alphabet: bool,
+
+    #[arg(
+        long = "dedupe",
+        help = "Collapse records with identical sequences into one"
+    )]
+    pub dedupe: bool,
+
+    #[arg(
+        long = "dedupe-keep",
+        value_enum,
+        default_value_t = DedupeKeep::First,
+        requires = "dedupe",
+        help = "Which of a set of identical records is kept, the first in the input or the smallest ID"
+    )]
+    pub dedupe_keep: DedupeKeep,
+
+    #[arg(
+        long = "dedupe-prefer",
+        requires = "dedupe",
+        help = "Regex on the header, a record matching it is kept over ones that don't, e.g. '^>NP_'"
+    )]
+    pub dedupe_prefer: Option<String>,
+
+    #[arg(
+        long = "dedupe-substrings",
+        requires = "dedupe",
+        help = "Also collapse records whose sequence is found whole inside a longer one"
+    )]
+    pub dedupe_substrings: bool,
+
+    #[arg(
+        long = "translate",
+        help = "Translate -d cds input and write the peptides to the pep folder"
+    )]
+    pub translate: bool,
+
+    #[arg(
+        long = "genetic-code",
+        default_value_t = 1,
+        requires = "translate",
+        help = "NCBI genetic code table used by --translate"
+    )]
+    pub genetic_code: u8,
+
+    #[arg(
+        long = "dry-run",
+        help = "Work out and print the output files without writing anything"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long = "plan-json",
+        requires = "dry_run",
+        help = "Also write the --dry-run plan to this file as JSON"
+    )]
+    pub plan_json: Option<String>,
+
+    #[arg(
+        long = "resume",
+        conflicts_with_all = ["force", "skip_existing"],
+        help = "Carry on from the first missing or incomplete file listed in the manifest"
+    )]
+    pub resume: bool,
+
+    // The command recorded in the manifest when it isn't this process's
+    // own, prepare-batch sets it to the row's splitcount/sizesplit line
+    #[arg(skip)]
+    pub command_line: Option<Vec<String>>,
+}
+
+impl SplitOptions {
+    pub fn dedupe_settings(&self) -> String {
+        match self.dedupe {
+            true => format!(
+                "keep={:?},prefer={:?},substrings={}",
+                self.dedupe_keep, self.dedupe_prefer, self.dedupe_substrings
+            ),
+            false => "false".to_string(),
+        }
+    }
+
+    pub fn qc_settings(&self) -> String {
+        format!(
+            "min_length={:?},max_length={:?},max_ambiguous={:?},drop_internal_stops={},check_cds={},check_alphabet={}",
+            self.min_length,
+            self.max_length,
+            self.max_ambiguous,
+            self.drop_internal_stops,
+            self.check_cds,
+            self.check_alphabet
+        )
+    }
+
+    pub fn settings(&self) -> String {
+        // Recorded in the manifest so --resume can tell if anything changed
+        format!(
+            "data_type={:?} label={:?} origin_db={:?} clean_headers={} dialect={:?} on_collision={:?} header_format={:?} annotation={:?} with_location={} longest_isoform={} qc={} dedupe={} translate={:?} file_name={:?}",
+            self.data_type(),
+            self.label,
+            self.origin_db(),
+            self.sanitise,
+            self.dialect_name(),
+            self.on_collision,
+            self.header_format,
+            self.annotation,
+            self.with_location,
+            self.longest_isoform,
+            self.qc_settings(),
+            self.dedupe_settings(),
+            self.translate.then_some(self.genetic_code),
+            self.file_name
+        )
+    }
+
+    // -d and -r are filled in by detection before a split starts
+    pub fn data_type(&self) -> DType {
+        self.data_type.expect("data type is detected before use")
+    }
+
+    pub fn origin_db(&self) -> OriginType {
+        self.origin_db.unwrap_or(OriginType::Other)
+    }
+
+    pub fn dialect_name(&self) -> Option<String> {
+        // -r ensembl and -r ncbi have built-in dialects of the same name
+        match (&self.dialect, self.origin_db()) {
+            (Some(dialect), _) => Some(dialect.clone()),
+            (None, OriginType::Ensembl) => Some("ensembl".to_string()),
+            (None, OriginType::Ncbi) => Some("ncbi".to_string()),
+            (None, OriginType::Other) => None,
+        }
+    }
+
+    pub fn existing_files(&self) -> ExistingFiles {
+        // A resumed run rewrites anything after the last complete file
+        if self.force || self.resume {
+            ExistingFiles::Overwrite
+        } else if self.skip_existing {
+            ExistingFiles::Skip
+        } else {
+            ExistingFiles::Fail
+        }
+    }
+}
+
 // CLI
+/// Options for splitgroup
+#[derive(Args, Debug, Clone)]
+pub struct GroupOptions {
+    #[arg(short = 'f', long, help = "Path to the input FASTA file")]
+    pub fasta_file: String,
+
+    #[arg(
+        short = 'o',
+        long = "output-directory",
+        default_value = "./",
+        help = "The output directory that files will be placed in"
+    )]
+    pub outpath: String,
+
+    #[arg(
+        long = "regex",
+        conflicts_with_all = ["field", "map"],
+        help = "Regex on the header, the group is its (?<group>...) capture or else the whole match"
+    )]
+    pub regex: Option<String>,
+
+    #[arg(
+        long = "field",
+        conflicts_with = "map",
+        value_parser = ["protein_id", "gene_id", "transcript_id", "biotype", "source", "location", "gene_name", "chromosome"],
+        help = "Header field to group by, read with --dialect and/or --annotation. chromosome is the sequence of the location"
+    )]
+    pub field: Option<String>,
+
+    #[arg(
+        long = "map",
+        help = "TSV of record ID and group, IDs are matched with and without a version"
+    )]
+    pub map: Option<String>,
+
+    #[arg(
+        long = "dialect",
+        help = "Header dialect --field reads the header with"
+    )]
+    pub dialect: Option<String>,
+
+    #[arg(
+        long = "rules",
+        requires = "dialect",
+        help = "YAML or TOML file of extra header dialects for --dialect"
+    )]
+    pub rules: Option<String>,
+
+    #[arg(
+        long = "annotation",
+        requires = "field",
+        help = "GFF3 or GTF filling in the --field values the header doesn't have"
+    )]
+    pub annotation: Option<String>,
+
+    #[arg(
+        long = "ungrouped",
+        default_value = "ungrouped",
+        help = "Group for records with no group found"
+    )]
+    pub ungrouped: String,
+
+    #[arg(
+        long = "accession",
+        alias = "name",
+        help = "Fills in {accession} in --file-name, worked out from the input file name if not given"
+    )]
+    pub accession: Option<String>,
+
+    #[arg(
+        long = "file-name",
+        default_value = GROUP_FILE_NAME,
+        help = "Name of each group's file, from {accession} and {group}"
+    )]
+    pub file_name: String,
+
+    #[arg(
+        long = "max-open",
+        default_value_t = MAX_OPEN,
+        value_parser = clap::value_parser!(usize),
+        help = "Most group files open at once, others are closed and reopened as needed"
+    )]
+    pub max_open: usize,
+
+    #[arg(long = "force", help = "Overwrite group files that already exist")]
+    pub force: bool,
+}
+
 #[derive(Parser, Debug)]
 #[command(
     name = "Treeval UTILS",
@@ -53,175 +571,197 @@ pub struct Cli {
 pub enum Commands {
     #[command(
         name = "sizesplit",
+        alias = "sizesplit2",
         about = "Prepare the Geneset data into the expected structure",
-        long_about = format!("This function will split the input genome into chunks roughly equal to a user given amount of memory (given in bytes, e.g. 1000000 == 1mb). The input will be split into as close to the user given about as possible, for example if a scaffold is larger than 1mb, it will not be split up.")
+        long_about = format!("This function will split the input geneset into chunks holding roughly a user given amount of sequence (in bytes of sequence, one per residue, e.g. 1000000 == 1mb, the default). The input will be split into as close to the user given amount as possible, but a record is never split up, so a record larger than 1mb gets a file to itself.")
     )]
     PrepGenesetBySize {
-        // Path to the TreeVal yaml file generated by the user
-        #[arg(short = 'f', long, help = "Path to the input FASTA file")]
-        fasta_file: String,
-
         // Chunk size
         #[arg(
             short = 's',
             long = "memory_size",
-            default_value_t = 50,
+            default_value_t = 1_000_000,
             value_parser = clap::value_parser!(usize),
-            help="Size, in Megabyes, that the output files should try to match"
+            help="Size, in bytes of sequence, that the output files should try to match (1000000 == 1mb)"
         )]
         chunk_size: usize,
 
-        #[arg(
-            short = 'd',
-            long = "data_type",
-            value_enum,
-            help = "Data type of the input fasta file"
-        )]
-        data_type: DType,
+        #[command(flatten)]
+        options: SplitOptions,
 
         #[arg(
-            short = 'r',
-            long = "origin_db",
+            long = "strategy",
             value_enum,
-            help = "Data type of the input fasta file"
+            default_value_t = SplitStrategy::Stream,
+            help = "How records are packed into the output files",
+            long_help = "stream: fill files in input order until the next record would go over --memory_size\nfirst-fit-decreasing: pack the largest records first into as few files of --memory_size as possible\nequal-bins: spread the records over --bins files of near equal size"
         )]
-        origin_db: OriginType,
+        strategy: SplitStrategy,
 
         #[arg(
-            short = 'c',
-            long = "clean_headers",
-            value_parser = clap::value_parser!(bool),
-            help = "Choose whether to clean the headers"
-        )]
-        sanitise: bool,
-
-        #[arg(
-            short = 'o',
-            long = "output-directory",
-            default_value = "./",
-            help = "The output directory that files will be placed in"
+            short = 'n',
+            long = "bins",
+            value_parser = clap::value_parser!(usize),
+            help = "Number of output files to aim for, used with --strategy equal-bins"
         )]
-        outpath: String,
+        bins: Option<usize>,
     },
+
     #[command(
-        name = "sizesplit2",
+        name = "splitcount",
         about = "Prepare the Geneset data into the expected structure",
-        long_about = format!("This function will split the input genome into chunks roughly equal to a user given amount of memory (given in bytes, e.g. 1000000 == 1mb). The input will be split into as close to the user given about as possible, for example if a scaffold is larger than 1mb, it will not be split up.")
+        long_about = format!("Split the input genome into the user given number of records per file, e.g. -s 100 will split the genome into 100 records per file.")
     )]
-    PrepGenesetBySize2 {
-        // Path to the TreeVal yaml file generated by the user
-        #[arg(short = 'f', long, help = "Path to the input FASTA file")]
-        fasta_file: String,
-
+    PrepGenesetByCount {
         // Chunk size
         #[arg(
             short = 's',
-            long = "memory_size",
-            default_value_t = 50,
+            long = "split_into",
+            default_value_t = 100,
             value_parser = clap::value_parser!(usize),
-            help="Size, in Megabyes, that the output files should try to match"
+            help="Number of records to split the input genome into."
         )]
         chunk_size: usize,
 
         #[arg(
-            short = 'd',
-            long = "data_type",
-            value_enum,
-            help = "Data type of the input fasta file"
+            short = 'n',
+            long = "n-files",
+            conflicts_with = "chunk_size",
+            value_parser = clap::value_parser!(usize),
+            help = "Split into this many files of near equal record counts, in place of -s"
         )]
-        data_type: DType,
+        n_files: Option<usize>,
 
         #[arg(
-            short = 'r',
-            long = "origin_db",
+            long = "n-files-mode",
             value_enum,
-            help = "Data type of the input fasta file"
+            default_value_t = NFilesMode::Contiguous,
+            requires = "n_files",
+            help = "How --n-files shares out the records",
+            long_help = "contiguous: each file takes the next run of records in input order, the count comes from the .fai if there is one, otherwise a quick pass over the input\nround-robin: record 1 goes to file 1, record 2 to file 2 and so on, each file is streamed to a temp file and renamed into place at the end"
         )]
-        origin_db: OriginType,
+        n_files_mode: NFilesMode,
 
-        #[arg(
-            short = 'c',
-            long = "clean_headers",
-            value_parser = clap::value_parser!(bool),
-            help = "Choose whether to clean the headers"
-        )]
-        sanitise: bool,
+        #[command(flatten)]
+        options: SplitOptions,
+    },
+
+    #[command(
+        name = "splitgroup",
+        about = "Split the input into one file per group of records",
+        long_about = "Write one FASTA per group, the group of each record coming from a --regex on its header, a header --field read with a dialect or an annotation, or a --map of ID to group. A summary of the groups is written next to them."
+    )]
+    SplitGroup {
+        #[command(flatten)]
+        options: GroupOptions,
+    },
 
+    #[command(
+        name = "generate_csv",
+        about = "Generate a CSV file which describes the GENESET folder structure",
+        long_about = "Given the directory containing the GENESET data, this function will generate csv's for each geneset contained inside"
+    )]
+    GenerateCSV {
         #[arg(
-            short = 'o',
-            long = "output-directory",
+            short = 'i',
+            long = "input-directory",
             default_value = "./",
             help = "The output directory that files will be placed in"
         )]
-        outpath: String,
+        folder_path: String,
+
+        #[arg(
+            long = "layout",
+            default_value = GENESET_LAYOUT,
+            help = "Folders under -i the data is in, from {clade}, {organism}, {accession} and {type}"
+        )]
+        layout: String,
     },
 
     #[command(
-        name = "splitcount",
-        about = "Prepare the Geneset data into the expected structure",
-        long_about = format!("Split the input genome into the user given number of records per file, e.g. -s 100 will split the genome into 100 records per file.")
+        name = "prepare-batch",
+        about = "Split many inputs from a TSV/YAML manifest into the GENESET folder structure",
+        long_about = "Reads a manifest with one row per input: fasta, clade, organism and accession, plus optional data_type, origin_db, split (count or size), chunk_size, n_files, strategy, bins, label and args. Each row is split into {output}/{clade}/{organism}/{accession}/{type}/ as splitcount or sizesplit would, several at a time with --jobs, then the CSVs are generated once at the end."
     )]
-    PrepGenesetByCount {
-        // Path to the TreeVal yaml file generated by the user
-        #[arg(short = 'f', long, help = "Path to the input FASTA file")]
-        fasta_file: String,
+    PrepareBatch {
+        #[arg(
+            short = 'm',
+            long = "manifest",
+            help = "TSV with a header row, or a YAML list when it ends in .yaml/.yml"
+        )]
+        manifest: String,
 
-        // Chunk size
         #[arg(
-            short = 's',
-            long = "split_into",
-            default_value_t = 100,
-            value_parser = clap::value_parser!(usize),
-            help="Number of records to split the input genome into."
+            short = 'o',
+            long = "outpath",
+            default_value = "./",
+            help = "The GENESET folder the clade folders go in"
         )]
-        chunk_size: usize,
+        outpath: String,
 
         #[arg(
-            short = 'd',
-            long = "data_type",
-            value_enum,
-            help = "Data type of the input fasta file"
+            long = "layout",
+            default_value = GENESET_LAYOUT,
+            help = "Folders under -o each input goes in, for the splits and generate_csv alike"
         )]
-        data_type: DType,
+        layout: String,
 
         #[arg(
-            short = 'r',
-            long = "origin_db",
-            value_enum,
-            help = "Data type of the input fasta file"
+            short = 'j',
+            long = "jobs",
+            default_value_t = 1,
+            value_parser = clap::value_parser!(usize),
+            help = "Number of inputs to split at once"
         )]
-        origin_db: OriginType,
+        jobs: usize,
 
         #[arg(
-            short = 'c',
-            long = "clean_headers",
-            value_parser = clap::value_parser!(bool),
-            help = "Choose whether to clean the headers"
+            long = "args",
+            allow_hyphen_values = true,
+            help = "Split options added to every row, e.g. \"-c --dialect ensembl\""
         )]
-        sanitise: bool,
+        args: Option<String>,
 
         #[arg(
-            short = 'o',
-            long = "output-directory",
-            default_value = "./",
-            help = "The output directory that files will be placed in"
+            long = "dry-run",
+            help = "Check the manifest and print what each row would write, without writing anything"
         )]
-        outpath: String,
+        dry_run: bool,
     },
 
     #[command(
-        name = "generate_csv",
-        about = "Generate a CSV file which describes the GENESET folder structure",
-        long_about = "Given the directory containing the GENESET data, this function will generate csv's for each geneset contained inside"
+        name = "restore-headers",
+        about = "Put the original headers back into files made from sanitised chunks",
+        long_about = "Uses the rename map written by a --clean_headers split to swap sanitised headers back to the original IDs in a FASTA, BED or PAF file. FASTA headers get the full original definition line, BED (chrom and name) and PAF (query and target) columns get the original ID."
     )]
-    GenerateCSV {
+    RestoreHeaders {
+        #[arg(
+            short = 'm',
+            long = "rename-map",
+            help = "The $data_type_rename_map.tsv written by the split"
+        )]
+        map_file: String,
+
         #[arg(
             short = 'i',
-            long = "input-directory",
-            default_value = "./",
-            help = "The output directory that files will be placed in"
+            long = "input",
+            help = "FASTA, BED or PAF file to restore"
         )]
-        folder_path: String,
+        input: String,
+
+        #[arg(
+            short = 'o',
+            long = "output",
+            help = "Where to write the restored file, stdout if not given"
+        )]
+        output: Option<String>,
+
+        #[arg(
+            long = "format",
+            value_enum,
+            help = "Format of the input, guessed from its suffix if not given"
+        )]
+        format: Option<RestoreFormat>,
     },
 
     #[command(
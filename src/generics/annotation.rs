@@ -0,0 +1,608 @@
+use super::header::{gff_unescape, HeaderFields};
+use super::open_text;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Feature types that are parts of a transcript rather than one
+const PARTS: [&str; 9] = [
+    "exon",
+    "CDS",
+    "five_prime_UTR",
+    "three_prime_UTR",
+    "UTR",
+    "start_codon",
+    "stop_codon",
+    "intron",
+    "Selenocysteine",
+];
+
+// The prefixes Ensembl and NCBI put in front of the IDs in a GFF3
+const ID_PREFIXES: [&str; 6] = ["gene:", "transcript:", "CDS:", "gene-", "rna-", "cds-"];
+
+fn strip_prefix(id: &str) -> &str {
+    ID_PREFIXES
+        .iter()
+        .find_map(|prefix| id.strip_prefix(prefix))
+        .unwrap_or(id)
+}
+
+fn unversioned(id: &str) -> Option<&str> {
+    // ENSP00000354587.3 -> ENSP00000354587
+    let (base, version) = id.rsplit_once('.')?;
+    if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()) {
+        Some(base)
+    } else {
+        None
+    }
+}
+
+/// seqid:start-end(strand), widened as more of the feature is seen
+#[derive(Debug, Clone)]
+struct Span {
+    seqid: String,
+    start: u64,
+    end: u64,
+    strand: String,
+}
+
+impl Span {
+    fn widen(span: &mut Option<Span>, other: &Span) {
+        match span {
+            Some(span) if span.seqid == other.seqid => {
+                span.start = span.start.min(other.start);
+                span.end = span.end.max(other.end);
+            }
+            Some(_) => {}
+            None => *span = Some(other.clone()),
+        }
+    }
+
+    fn to_field(&self) -> String {
+        format!(
+            "{}:{}-{}({})",
+            self.seqid, self.start, self.end, self.strand
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Gene {
+    id: Option<String>,
+    name: Option<String>,
+    biotype: Option<String>,
+    span: Option<Span>,
+}
+
+#[derive(Debug, Default)]
+struct Transcript {
+    id: Option<String>,
+    gene: Option<String>,
+    protein: Option<String>,
+    name: Option<String>,
+    biotype: Option<String>,
+    source: Option<String>,
+    span: Option<Span>,
+    own_span: bool,
+    // Every ID in the file that should find this transcript
+    keys: Vec<String>,
+}
+
+/// One line of a GFF3 or GTF
+struct Feature {
+    is_gtf: bool,
+    source: String,
+    kind: String,
+    span: Span,
+    attributes: BTreeMap<String, String>,
+}
+
+impl Feature {
+    fn parse(line: &str) -> Option<Feature> {
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() < 9 {
+            return None;
+        }
+        // GTF is key "value"; GFF3 is key=value
+        let is_gtf = !columns[8]
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .contains('=');
+        let attributes = columns[8]
+            .split(';')
+            .filter_map(|part| {
+                let part = part.trim();
+                if is_gtf {
+                    let (key, value) = part.split_once(' ')?;
+                    Some((key.to_string(), value.trim().trim_matches('"').to_string()))
+                } else {
+                    let (key, value) = part.split_once('=')?;
+                    // Parent can list several, the first will do
+                    let value = value.split(',').next().unwrap_or_default();
+                    Some((key.to_string(), gff_unescape(value)))
+                }
+            })
+            .collect();
+        Some(Feature {
+            is_gtf,
+            source: columns[1].replace(' ', "_"),
+            kind: columns[2].to_string(),
+            span: Span {
+                seqid: columns[0].to_string(),
+                start: columns[3].parse().ok()?,
+                end: columns[4].parse().ok()?,
+                strand: columns[6].to_string(),
+            },
+            attributes,
+        })
+    }
+
+    fn get(&self, keys: &[&str]) -> Option<String> {
+        // Values end up in a header, so any spaces become _
+        keys.iter()
+            .find_map(|key| self.attributes.get(*key))
+            .filter(|value| !value.is_empty())
+            .map(|value| value.split_whitespace().collect::<Vec<_>>().join("_"))
+    }
+
+    fn versioned_id(&self, key: &str) -> Option<String> {
+        // Ensembl GTFs keep the version of gene_id etc. in gene_version
+        let id = self.get(&[key])?;
+        let version_key = format!("{}_version", key.trim_end_matches("_id"));
+        match self.get(&[&version_key]) {
+            Some(version) if self.is_gtf => Some(format!("{}.{}", id, version)),
+            _ => Some(id),
+        }
+    }
+}
+
+/// Header fields for every transcript in a GFF3/GTF, found by any of its IDs
+#[derive(Debug)]
+pub struct Annotation {
+    entries: Vec<HeaderFields>,
+    index: HashMap<String, usize>,
+    pub matched: AtomicUsize,
+    pub missed: AtomicUsize,
+}
+
+impl Annotation {
+    pub fn load(path: &str, with_location: bool) -> io::Result<Self> {
+        let mut genes: HashMap<String, Gene> = HashMap::new();
+        let mut transcripts: HashMap<String, Transcript> = HashMap::new();
+        // Transcript keys in file order, so IDs shared by two are given
+        // to the same one every run
+        let mut order: Vec<String> = Vec::new();
+
+        for line in open_text(path)?.lines() {
+            let line = line?;
+            if line.starts_with("##FASTA") {
+                break;
+            }
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let Some(feature) = Feature::parse(&line) else {
+                continue;
+            };
+            let is_part = PARTS.contains(&feature.kind.as_str());
+
+            // GTF lines all name their gene and transcript, GFF3 features
+            // have an ID and point to their Parent
+            let (gene_key, transcript_key, parent) = if feature.is_gtf {
+                (
+                    feature.get(&["gene_id"]),
+                    feature.get(&["transcript_id"]),
+                    feature.get(&["gene_id"]),
+                )
+            } else if feature.kind.ends_with("gene") {
+                (feature.get(&["ID"]), None, None)
+            } else if is_part {
+                let parent = feature.get(&["Parent"]);
+                match parent {
+                    // A CDS straight under a gene, as in prokaryote GFFs,
+                    // stands in for its own transcript
+                    Some(parent) if genes.contains_key(&parent) => {
+                        (None, feature.get(&["ID", "protein_id"]), Some(parent))
+                    }
+                    parent => (None, parent, None),
+                }
+            } else {
+                (None, feature.get(&["ID"]), feature.get(&["Parent"]))
+            };
+
+            if let Some(key) = gene_key {
+                let gene = genes.entry(key.clone()).or_default();
+                gene.id = gene
+                    .id
+                    .take()
+                    .or(feature.versioned_id("gene_id"))
+                    .or(Some(strip_prefix(&key).to_string()));
+                gene.name = gene
+                    .name
+                    .take()
+                    .or(feature.get(&["gene_name", "Name", "gene"]));
+                gene.biotype =
+                    gene.biotype
+                        .take()
+                        .or(feature.get(&["gene_biotype", "gene_type", "biotype"]));
+                Span::widen(&mut gene.span, &feature.span);
+            }
+
+            let Some(key) = transcript_key else {
+                continue;
+            };
+            if !transcripts.contains_key(&key) {
+                order.push(key.clone());
+            }
+            let transcript = transcripts.entry(key.clone()).or_default();
+            if transcript.keys.is_empty() {
+                transcript.keys.push(key.clone());
+            }
+            if transcript.gene.is_none() {
+                transcript.gene = parent;
+            }
+            if !is_part || feature.is_gtf {
+                transcript.id = transcript
+                    .id
+                    .take()
+                    .or(feature.versioned_id("transcript_id"))
+                    .or(Some(strip_prefix(&key).to_string()));
+                transcript.name = transcript
+                    .name
+                    .take()
+                    .or(feature.get(&["Name", "transcript_name"]));
+                transcript.biotype = transcript.biotype.take().or(feature.get(&[
+                    "transcript_biotype",
+                    "transcript_type",
+                    "biotype",
+                ]));
+            }
+            transcript.source.get_or_insert(feature.source.clone());
+            if feature.kind == "CDS" {
+                if let Some(protein) = feature.versioned_id("protein_id") {
+                    transcript.protein.get_or_insert(protein);
+                }
+                if let Some(id) = feature.get(&["ID"]) {
+                    if !transcript.keys.contains(&id) {
+                        transcript.keys.push(id);
+                    }
+                }
+            }
+
+            // A transcript's own line gives its span, without one the
+            // parts are added up
+            if !is_part {
+                transcript.span = Some(feature.span.clone());
+                transcript.own_span = true;
+            } else if !transcript.own_span {
+                Span::widen(&mut transcript.span, &feature.span);
+            }
+        }
+
+        let mut annotation = Annotation {
+            entries: Vec::new(),
+            index: HashMap::new(),
+            matched: AtomicUsize::new(0),
+            missed: AtomicUsize::new(0),
+        };
+        let mut genes_used = 0;
+        for key in &order {
+            let transcript = &transcripts[key];
+            let gene = transcript.gene.as_ref().and_then(|gene| genes.get(gene));
+            if gene.is_some() {
+                genes_used += 1;
+            }
+            let mut fields = HeaderFields {
+                protein: transcript.protein.clone(),
+                gene: gene.and_then(|gene| gene.id.clone()).or(transcript
+                    .gene
+                    .as_deref()
+                    .map(|id| strip_prefix(id).to_string())),
+                transcript: transcript.id.clone(),
+                symbol: gene.and_then(|gene| gene.name.clone()),
+                biotype: transcript
+                    .biotype
+                    .clone()
+                    .or(gene.and_then(|gene| gene.biotype.clone())),
+                source: transcript.source.clone(),
+                location: None,
+            };
+            if with_location {
+                fields.location = transcript
+                    .span
+                    .as_ref()
+                    .or(gene.and_then(|gene| gene.span.as_ref()))
+                    .map(Span::to_field);
+            }
+            let mut keys = transcript.keys.clone();
+            keys.extend(fields.transcript.clone());
+            keys.extend(fields.protein.clone());
+            keys.extend(transcript.name.clone());
+            annotation.add(fields, keys);
+        }
+
+        // Genes with nothing under them, so a geneset keyed by gene ID
+        // still finds its record
+        let parents: HashSet<&String> = transcripts
+            .values()
+            .filter_map(|transcript| transcript.gene.as_ref())
+            .collect();
+        let mut lone_genes: Vec<(&String, &Gene)> = genes
+            .iter()
+            .filter(|(key, _)| !parents.contains(key))
+            .collect();
+        lone_genes.sort_by_key(|(key, _)| key.as_str());
+        for (key, gene) in lone_genes {
+            let fields = HeaderFields {
+                gene: gene.id.clone(),
+                symbol: gene.name.clone(),
+                biotype: gene.biotype.clone(),
+                location: if with_location {
+                    gene.span.as_ref().map(Span::to_field)
+                } else {
+                    None
+                },
+                ..Default::default()
+            };
+            let mut keys = vec![key.clone()];
+            keys.extend(gene.id.clone());
+            annotation.add(fields, keys);
+        }
+
+        println!(
+            "Loaded {} transcripts ({} with a gene) and {} genes from annotation: {}",
+            transcripts.len(),
+            genes_used,
+            genes.len(),
+            path
+        );
+        Ok(annotation)
+    }
+
+    fn add(&mut self, fields: HeaderFields, keys: Vec<String>) {
+        // An ID already taken stays with the first feature that had it
+        let entry = self.entries.len();
+        for key in keys {
+            if let Some(base) = unversioned(&key) {
+                self.index.entry(base.to_string()).or_insert(entry);
+            }
+            self.index.entry(key).or_insert(entry);
+        }
+        self.entries.push(fields);
+    }
+
+    fn find(&self, id: &str) -> Option<usize> {
+        // As it is, without a gene:/rna- style prefix, then without a version
+        self.index
+            .get(id)
+            .or_else(|| self.index.get(strip_prefix(id)))
+            .or_else(|| unversioned(id).and_then(|base| self.index.get(base)))
+            .copied()
+    }
+
+    pub fn lookup(&self, header: &str) -> Option<&HeaderFields> {
+        // By the first word of the header, then each of the IDs in a
+        // pipe separated one, >ENSP...|ENST...|ENSG...
+        let word = header
+            .trim_start_matches('>')
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        std::iter::once(word)
+            .chain(word.split('|'))
+            .find_map(|id| self.find(id))
+            .map(|entry| &self.entries[entry])
+    }
+
+    pub fn count(&self, matched: bool) {
+        // Once for each record written, lookups alone aren't counted as
+        // --resume looks the records it skips up again to check them
+        match matched {
+            true => self.matched.fetch_add(1, Ordering::Relaxed),
+            false => self.missed.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn report(&self) {
+        println!(
+            "{} records were joined to the annotation, {} had no match",
+            self.matched.load(Ordering::Relaxed),
+            self.missed.load(Ordering::Relaxed)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::scratch::Scratch;
+    use std::fs;
+
+    fn load(name: &str, lines: &[&str], with_location: bool) -> Annotation {
+        let dir = Scratch::new(name);
+        let path = dir.join("annotation");
+        fs::write(&path, lines.join("\n")).unwrap();
+        Annotation::load(path.to_str().unwrap(), with_location).unwrap()
+    }
+
+    fn value(field: &Option<String>) -> &str {
+        field.as_deref().unwrap_or("-")
+    }
+
+    fn resolved(annotation: &Annotation, header: &str) -> [String; 6] {
+        // protein, gene, transcript, symbol, biotype, location
+        let fields = annotation.lookup(header).expect(header);
+        [
+            &fields.protein,
+            &fields.gene,
+            &fields.transcript,
+            &fields.symbol,
+            &fields.biotype,
+            &fields.location,
+        ]
+        .map(|field| value(field).to_string())
+    }
+
+    const ENSEMBL_GFF3: [&str; 7] = [
+        "##gff-version 3",
+        "1\tensembl\tgene\t100\t900\t.\t+\t.\tID=gene:ENSG01;Name=ABC;biotype=protein_coding;gene_id=ENSG01;version=3",
+        "1\thavana\tmRNA\t100\t800\t.\t+\t.\tID=transcript:ENST01;Parent=gene:ENSG01;Name=ABC-201;biotype=protein_coding;transcript_id=ENST01;version=1",
+        "1\thavana\texon\t100\t300\t.\t+\t.\tParent=transcript:ENST01",
+        "1\thavana\tCDS\t150\t300\t.\t+\t0\tID=CDS:ENSP01;Parent=transcript:ENST01;protein_id=ENSP01",
+        "1\thavana\tCDS\t500\t700\t.\t+\t0\tID=CDS:ENSP01;Parent=transcript:ENST01;protein_id=ENSP01",
+        "1\tensembl\tncRNA_gene\t2000\t2100\t.\t-\t.\tID=gene:ENSG09;Name=MIR1;biotype=miRNA;gene_id=ENSG09",
+    ];
+
+    #[test]
+    fn gff3_transcripts_are_found_by_any_of_their_ids() {
+        let annotation = load("annotation-gff3", &ENSEMBL_GFF3, true);
+        let expected = [
+            "ENSP01",
+            "ENSG01",
+            "ENST01",
+            "ABC",
+            "protein_coding",
+            "1:100-800(+)",
+        ];
+        for header in [
+            ">ENSP01 pep",
+            ">ENST01",
+            ">CDS:ENSP01",
+            ">transcript:ENST01",
+            // Without the version the file doesn't have
+            ">ENSP01.4",
+            ">ABC-201",
+        ] {
+            assert_eq!(resolved(&annotation, header), expected, "{}", header);
+        }
+        assert_eq!(
+            annotation.lookup(">ENSP01").unwrap().source.as_deref(),
+            Some("havana")
+        );
+    }
+
+    #[test]
+    fn lone_genes_are_found_by_gene_id() {
+        let annotation = load("annotation-lone", &ENSEMBL_GFF3, true);
+        assert_eq!(
+            resolved(&annotation, ">ENSG09.1"),
+            ["-", "ENSG09", "-", "MIR1", "miRNA", "1:2000-2100(-)"]
+        );
+        // A gene with transcripts isn't an entry of its own
+        assert!(annotation.lookup(">ENSG01").is_none());
+        assert!(annotation.lookup(">ENSP99").is_none());
+    }
+
+    #[test]
+    fn pipe_separated_headers_are_tried_id_by_id() {
+        let annotation = load("annotation-pipe", &ENSEMBL_GFF3, false);
+        assert_eq!(
+            resolved(&annotation, ">ENSP99.1|ENST01.1|ENSG01.3 description"),
+            ["ENSP01", "ENSG01", "ENST01", "ABC", "protein_coding", "-"]
+        );
+        assert!(annotation.lookup(">ENSP99|ENST99").is_none());
+    }
+
+    #[test]
+    fn prokaryote_cds_under_a_gene_stands_for_its_transcript() {
+        let annotation = load(
+            "annotation-prokaryote",
+            &[
+                "NC_000913.3\tRefSeq\tgene\t190\t255\t.\t+\t.\tID=gene-b0001;Name=thrL;gene_biotype=protein_coding",
+                "NC_000913.3\tRefSeq\tCDS\t190\t200\t.\t+\t0\tID=cds-NP_414542.1;Parent=gene-b0001;protein_id=NP_414542.1",
+                "NC_000913.3\tRefSeq\tCDS\t230\t255\t.\t+\t0\tID=cds-NP_414542.1;Parent=gene-b0001;protein_id=NP_414542.1",
+            ],
+            true,
+        );
+        // The CDS parts are added up for the span
+        assert_eq!(
+            resolved(&annotation, ">NP_414542.1"),
+            [
+                "NP_414542.1",
+                "b0001",
+                "-",
+                "thrL",
+                "protein_coding",
+                "NC_000913.3:190-255(+)"
+            ]
+        );
+    }
+
+    #[test]
+    fn parts_with_several_parents_go_to_the_first() {
+        let annotation = load(
+            "annotation-parents",
+            &[
+                "chr2\tGnomon\tgene\t1\t500\t.\t-\t.\tID=gene-XYZ;Name=XYZ;gene_biotype=protein_coding",
+                "chr2\tGnomon\tmRNA\t1\t500\t.\t-\t.\tID=rna-XM_1.1;Parent=gene-XYZ",
+                "chr2\tGnomon\tmRNA\t5\t400\t.\t-\t.\tID=rna-XM_2.1;Parent=gene-XYZ",
+                "chr2\tGnomon\tCDS\t10\t90\t.\t-\t0\tID=cds-XP_1.1;Parent=rna-XM_1.1,rna-XM_2.1;protein_id=XP_1.1",
+            ],
+            true,
+        );
+        assert_eq!(
+            resolved(&annotation, ">XP_1.1"),
+            [
+                "XP_1.1",
+                "XYZ",
+                "XM_1.1",
+                "XYZ",
+                "protein_coding",
+                "chr2:1-500(-)"
+            ]
+        );
+        assert_eq!(
+            resolved(&annotation, ">XM_2.1"),
+            [
+                "-",
+                "XYZ",
+                "XM_2.1",
+                "XYZ",
+                "protein_coding",
+                "chr2:5-400(-)"
+            ]
+        );
+    }
+
+    #[test]
+    fn gtf_versions_are_added_to_the_ids() {
+        let attributes = "gene_id \"ENSG02\"; gene_version \"5\"; transcript_id \"ENST02\"; transcript_version \"2\"; gene_name \"XYZ\"; gene_biotype \"lncRNA\"; transcript_biotype \"protein_coding\";";
+        let annotation = load(
+            "annotation-gtf",
+            &[
+                &format!("3\tensembl\tgene\t10\t990\t.\t-\t.\t{}", attributes),
+                &format!("3\tensembl\ttranscript\t20\t980\t.\t-\t.\t{}", attributes),
+                &format!("3\tensembl\texon\t20\t400\t.\t-\t.\t{}", attributes),
+                &format!(
+                    "3\tensembl\tCDS\t50\t400\t.\t-\t0\t{} protein_id \"ENSP02\"; protein_version \"1\";",
+                    attributes
+                ),
+            ],
+            true,
+        );
+        let expected = [
+            "ENSP02.1",
+            "ENSG02.5",
+            "ENST02.2",
+            "XYZ",
+            "protein_coding",
+            "3:20-980(-)",
+        ];
+        for header in [">ENST02.2", ">ENST02", ">ENSP02.1", ">transcript:ENST02"] {
+            assert_eq!(resolved(&annotation, header), expected, "{}", header);
+        }
+    }
+
+    #[test]
+    fn lookups_alone_are_not_counted() {
+        let annotation = load("annotation-count", &ENSEMBL_GFF3, false);
+        annotation.lookup(">ENSP01");
+        annotation.lookup(">missing");
+        annotation.count(true);
+        annotation.count(false);
+        annotation.count(false);
+        assert_eq!(annotation.matched.load(Ordering::Relaxed), 1);
+        assert_eq!(annotation.missed.load(Ordering::Relaxed), 2);
+    }
+}
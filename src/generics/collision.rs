@@ -0,0 +1,256 @@
+use crate::cli::CollisionPolicy;
+use noodles::fasta::Record;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Condvar, Mutex};
+
+/// Lets threads take turns in sequence order, so work done under it
+/// comes out the same however the threads are scheduled
+pub struct InOrder<T> {
+    state: Mutex<(usize, T)>,
+    turn: Condvar,
+}
+
+impl<T> InOrder<T> {
+    pub fn new(value: T) -> Self {
+        InOrder {
+            state: Mutex::new((0, value)),
+            turn: Condvar::new(),
+        }
+    }
+
+    pub fn with<R>(&self, sequence: usize, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut state = self.state.lock().unwrap();
+        while state.0 != sequence {
+            state = self.turn.wait(state).unwrap();
+        }
+        let result = f(&mut state.1);
+        state.0 += 1;
+        self.turn.notify_all();
+        result
+    }
+
+    pub fn into_inner(self) -> T {
+        self.state.into_inner().unwrap().1
+    }
+}
+
+/// A record whose sanitised header had to be changed
+#[derive(Debug)]
+pub struct Collision {
+    pub original_id: String,
+    pub issue: String,
+    pub new_header: String,
+}
+
+/// Every sanitised header used so far in the run
+pub struct HeaderTracker {
+    policy: CollisionPolicy,
+    seen: HashSet<String>,
+    next_suffix: HashMap<String, usize>,
+    pub collisions: Vec<Collision>,
+}
+
+impl HeaderTracker {
+    pub fn new(policy: CollisionPolicy, kept: Vec<String>) -> Self {
+        // kept are the headers of chunks a --resume run isn't rewriting
+        HeaderTracker {
+            policy,
+            seen: kept.into_iter().collect(),
+            next_suffix: HashMap::new(),
+            collisions: Vec::new(),
+        }
+    }
+
+    fn with_suffix(&mut self, header: &str) -> String {
+        // The first free header_N, counting up from 2
+        let next = self.next_suffix.entry(header.to_string()).or_insert(2);
+        loop {
+            let candidate = format!("{}_{}", header, next);
+            *next += 1;
+            if !self.seen.contains(&candidate) {
+                return candidate;
+            }
+        }
+    }
+
+    pub fn resolve(&mut self, original: &Record, header: String) -> io::Result<(String, String)> {
+        // Returns the header to use, unique across the run, and the
+        // reason in the issue column of the rename map if it changed
+        let original_id = String::from_utf8_lossy(original.name()).to_string();
+        let issue = if header.trim().is_empty() {
+            "empty".to_string()
+        } else if self.seen.contains(&header) {
+            format!("duplicate {}", header)
+        } else {
+            self.seen.insert(header.clone());
+            return Ok((header, String::new()));
+        };
+
+        let new_header = match self.policy {
+            CollisionPolicy::Fail => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Record {} sanitised to a {} header, use --on-collision suffix or first-token to carry on",
+                        original_id, issue
+                    ),
+                ))
+            }
+            CollisionPolicy::Suffix if !header.trim().is_empty() => self.with_suffix(&header),
+            // An empty header has nothing to add a suffix to
+            CollisionPolicy::Suffix | CollisionPolicy::FirstToken => {
+                if self.seen.contains(&original_id) {
+                    self.with_suffix(&original_id)
+                } else {
+                    original_id.clone()
+                }
+            }
+        };
+        self.seen.insert(new_header.clone());
+        self.collisions.push(Collision {
+            original_id,
+            issue: issue.clone(),
+            new_header: new_header.clone(),
+        });
+        Ok((new_header, issue))
+    }
+
+    pub fn report(&self) {
+        // The full list is in the issue column of the rename map
+        if self.collisions.is_empty() {
+            return;
+        }
+        let empty = self
+            .collisions
+            .iter()
+            .filter(|collision| collision.issue == "empty")
+            .count();
+        println!(
+            "{} sanitised headers were changed ({} duplicate, {} empty):",
+            self.collisions.len(),
+            self.collisions.len() - empty,
+            empty
+        );
+        println!("Original\tIssue\tNew header");
+        for collision in self.collisions.iter().take(20) {
+            println!(
+                "{}\t{}\t{}",
+                collision.original_id, collision.issue, collision.new_header
+            );
+        }
+        if self.collisions.len() > 20 {
+            println!(
+                "... and {} more, see the issue column of the rename map",
+                self.collisions.len() - 20
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noodles::fasta::record::{Definition, Sequence};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn record(name: &str) -> Record {
+        Record::new(Definition::new(name, None), Sequence::from(b"MKV".to_vec()))
+    }
+
+    fn resolve_all(tracker: &mut HeaderTracker, headers: &[(&str, &str)]) -> Vec<String> {
+        headers
+            .iter()
+            .map(|(name, header)| {
+                tracker
+                    .resolve(&record(name), header.to_string())
+                    .unwrap()
+                    .0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn duplicates_get_the_next_free_suffix() {
+        // a_2 is taken by a kept chunk, so the first duplicate is a_3
+        let mut tracker = HeaderTracker::new(CollisionPolicy::Suffix, vec!["a_2".to_string()]);
+        let headers = resolve_all(
+            &mut tracker,
+            &[
+                ("r1", "a"),
+                ("r2", "a"),
+                ("r3", "b"),
+                ("r4", "a"),
+                ("r5", "a_4"),
+            ],
+        );
+        assert_eq!(headers, vec!["a", "a_3", "b", "a_4", "a_4_2"]);
+        let issues: Vec<&str> = tracker
+            .collisions
+            .iter()
+            .map(|collision| collision.issue.as_str())
+            .collect();
+        assert_eq!(issues, vec!["duplicate a", "duplicate a", "duplicate a_4"]);
+        assert_eq!(tracker.collisions[0].original_id, "r2");
+    }
+
+    #[test]
+    fn first_token_falls_back_to_the_original_id() {
+        let mut tracker = HeaderTracker::new(CollisionPolicy::FirstToken, Vec::new());
+        let headers = resolve_all(
+            &mut tracker,
+            &[("r1", "a"), ("r2", "a"), ("r2", "a"), ("r1", "b")],
+        );
+        // The original id can itself be taken, then it gets a suffix
+        assert_eq!(headers, vec!["a", "r2", "r2_2", "b"]);
+    }
+
+    #[test]
+    fn empty_headers_use_the_original_id() {
+        for policy in [CollisionPolicy::Suffix, CollisionPolicy::FirstToken] {
+            let mut tracker = HeaderTracker::new(policy, Vec::new());
+            let (header, issue) = tracker.resolve(&record("r1"), " ".to_string()).unwrap();
+            assert_eq!((header.as_str(), issue.as_str()), ("r1", "empty"));
+            let (header, _) = tracker.resolve(&record("r1"), String::new()).unwrap();
+            assert_eq!(header, "r1_2");
+        }
+    }
+
+    #[test]
+    fn fail_stops_at_the_first_collision() {
+        let mut tracker = HeaderTracker::new(CollisionPolicy::Fail, Vec::new());
+        assert_eq!(
+            resolve_all(&mut tracker, &[("r1", "a"), ("r2", "b")]),
+            vec!["a", "b"]
+        );
+
+        let error = tracker.resolve(&record("r3"), "a".to_string()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error
+            .to_string()
+            .contains("Record r3 sanitised to a duplicate a header"));
+        let error = tracker.resolve(&record("r4"), String::new()).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Record r4 sanitised to a empty header"));
+        assert!(tracker.collisions.is_empty());
+    }
+
+    #[test]
+    fn in_order_runs_in_sequence_order() {
+        let order = Arc::new(InOrder::new(Vec::new()));
+        let handles: Vec<_> = (0..8)
+            .rev()
+            .map(|sequence| {
+                let order = Arc::clone(&order);
+                thread::spawn(move || order.with(sequence, |seen| seen.push(sequence)))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let order = Arc::into_inner(order).unwrap();
+        assert_eq!(order.into_inner(), (0..8).collect::<Vec<usize>>());
+    }
+}
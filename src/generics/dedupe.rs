@@ -0,0 +1,334 @@
+use super::layout::Layout;
+use super::open_fasta;
+use crate::cli::{DedupeKeep, SplitOptions};
+use compare::{natural, Compare};
+use md5::{Digest, Md5};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Length of the seeds --dedupe-substrings looks for, anything shorter
+// is never collapsed into a longer record
+const SEED: usize = 12;
+const SEED_BASE: u64 = 1_000_003;
+
+pub fn dedupe_table_path(layout: &Layout) -> String {
+    // Next to the manifest, outside the data folder gencsv reads
+    layout.sidecar("dedupe.tsv")
+}
+
+fn normalise(sequence: &[u8]) -> Vec<u8> {
+    // Case and a trailing stop don't make two sequences different
+    let mut sequence = sequence.to_ascii_uppercase();
+    if sequence.last() == Some(&b'*') {
+        sequence.pop();
+    }
+    sequence
+}
+
+fn digest(sequence: &[u8]) -> [u8; 16] {
+    Md5::digest(sequence).into()
+}
+
+fn seed_hash(seed: &[u8]) -> u64 {
+    seed.iter().fold(0u64, |hash, c| {
+        hash.wrapping_mul(SEED_BASE).wrapping_add(*c as u64)
+    })
+}
+
+/// What is held for each record, a hash rather than the sequence so
+/// memory grows with the record count and not the size of the input
+struct Seen {
+    id: String,
+    preferred: bool,
+    length: usize,
+    hash: [u8; 16],
+    // Hash of the first SEED residues, for --dedupe-substrings
+    seed: Option<u64>,
+}
+
+impl Seen {
+    fn beats(&self, other: &Seen, rule: &DedupeKeep) -> bool {
+        // Whether this should represent the sequence rather than other,
+        // other always came first in the input
+        if self.preferred != other.preferred {
+            return self.preferred;
+        }
+        match rule {
+            DedupeKeep::First => false,
+            DedupeKeep::SmallestId => natural().compare(&self.id, &other.id) == Ordering::Less,
+        }
+    }
+}
+
+pub fn dedupe(
+    options: &SplitOptions,
+    keep: Option<Vec<bool>>,
+    table_file: &Path,
+) -> io::Result<Option<Vec<bool>>> {
+    // A pass over the input after QC and --longest-isoform, collapsing
+    // records with the same sequence into one. Returns the new keep
+    // mask and writes the table of what was collapsed into what
+    if !options.dedupe {
+        return Ok(keep);
+    }
+    let prefer = match &options.dedupe_prefer {
+        Some(pattern) => Some(Regex::new(pattern).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--dedupe-prefer: {}", e),
+            )
+        })?),
+        None => None,
+    };
+
+    let mut seen: Vec<Option<Seen>> = Vec::new();
+    let mut representative: HashMap<[u8; 16], usize> = HashMap::new();
+    let mut reader = open_fasta(&options.fasta_file)?;
+    for (index, result) in reader.records().enumerate() {
+        let record = result?;
+        if keep
+            .as_ref()
+            .is_some_and(|keep| keep.get(index) != Some(&true))
+        {
+            seen.push(None);
+            continue;
+        }
+        let sequence = normalise(record.sequence().as_ref());
+        let candidate = Seen {
+            id: String::from_utf8_lossy(record.name()).to_string(),
+            preferred: prefer
+                .as_ref()
+                .is_some_and(|re| re.is_match(&record.definition().to_string())),
+            length: sequence.len(),
+            hash: digest(&sequence),
+            seed: (sequence.len() >= SEED).then(|| seed_hash(&sequence[..SEED])),
+        };
+        match representative.get_mut(&candidate.hash) {
+            Some(best) => {
+                if candidate.beats(seen[*best].as_ref().unwrap(), &options.dedupe_keep) {
+                    *best = index;
+                }
+            }
+            None => {
+                representative.insert(candidate.hash, index);
+            }
+        }
+        seen.push(Some(candidate));
+    }
+
+    // Where each collapsed record went, and why
+    let mut collapsed: Vec<Option<(usize, &str)>> = vec![None; seen.len()];
+    for (index, record) in seen.iter().enumerate() {
+        if let Some(record) = record {
+            let best = representative[&record.hash];
+            if best != index {
+                collapsed[index] = Some((best, "identical"));
+            }
+        }
+    }
+    let identical = collapsed.iter().flatten().count();
+
+    if options.dedupe_substrings {
+        substrings(options, &seen, &mut collapsed)?;
+    }
+    let substring = collapsed.iter().flatten().count() - identical;
+
+    // A record collapsed into one that was itself collapsed ends up
+    // with the one at the end of the chain
+    let resolve = |mut index: usize| {
+        while let Some((into, _)) = collapsed[index] {
+            index = into;
+        }
+        index
+    };
+    let new_keep: Vec<bool> = seen
+        .iter()
+        .zip(&collapsed)
+        .map(|(record, collapsed)| record.is_some() && collapsed.is_none())
+        .collect();
+
+    println!(
+        "Dedupe: {} identical and {} substring records collapsed, keeping {} of {} records",
+        identical,
+        substring,
+        new_keep.iter().filter(|kept| **kept).count(),
+        seen.iter().flatten().count()
+    );
+    if !options.dry_run {
+        if let Some(parent) = table_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(table_file)?);
+        writeln!(writer, "representative_id\tcollapsed_id\treason")?;
+        for (index, collapsed) in collapsed.iter().enumerate() {
+            if let Some((_, reason)) = collapsed {
+                let kept = seen[resolve(index)].as_ref().unwrap();
+                let removed = seen[index].as_ref().unwrap();
+                writeln!(writer, "{}\t{}\t{}", kept.id, removed.id, reason)?;
+            }
+        }
+        writer.flush()?;
+        println!("Collapsed IDs written to: {}", table_file.display());
+    }
+    Ok(Some(new_keep))
+}
+
+fn substrings(
+    options: &SplitOptions,
+    seen: &[Option<Seen>],
+    collapsed: &mut [Option<(usize, &str)>],
+) -> io::Result<()> {
+    // Index the first SEED residues of every remaining sequence, then
+    // slide a SEED window along each one in another pass. Where a
+    // window matches the start of a shorter sequence, the hash of that
+    // stretch is checked against the shorter sequence's own hash, so
+    // no sequence has to be held in memory
+    let remaining: Vec<bool> = seen
+        .iter()
+        .zip(collapsed.iter())
+        .map(|(record, collapsed)| record.is_some() && collapsed.is_none())
+        .collect();
+    let mut starts: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, record) in seen.iter().enumerate() {
+        if let (true, Some(seed)) = (remaining[index], record.as_ref().and_then(|r| r.seed)) {
+            starts.entry(seed).or_default().push(index);
+        }
+    }
+
+    let top = SEED_BASE.wrapping_pow(SEED as u32 - 1);
+    let mut reader = open_fasta(&options.fasta_file)?;
+    for (index, result) in reader.records().enumerate() {
+        let record = result?;
+        if !remaining[index] {
+            continue;
+        }
+        let sequence = normalise(record.sequence().as_ref());
+        if sequence.len() <= SEED {
+            continue;
+        }
+        let mut hash = seed_hash(&sequence[..SEED]);
+        for position in 0..=sequence.len() - SEED {
+            if position > 0 {
+                hash = hash
+                    .wrapping_sub((sequence[position - 1] as u64).wrapping_mul(top))
+                    .wrapping_mul(SEED_BASE)
+                    .wrapping_add(sequence[position + SEED - 1] as u64);
+            }
+            let Some(candidates) = starts.get(&hash) else {
+                continue;
+            };
+            for candidate in candidates {
+                let shorter = seen[*candidate].as_ref().unwrap();
+                if collapsed[*candidate].is_some()
+                    || shorter.length >= sequence.len()
+                    || position + shorter.length > sequence.len()
+                {
+                    continue;
+                }
+                if digest(&sequence[position..position + shorter.length]) == shorter.hash {
+                    collapsed[*candidate] = Some((index, "substring"));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::scratch::Scratch;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Command {
+        #[command(flatten)]
+        options: SplitOptions,
+    }
+
+    fn scratch(name: &str, fasta: &str) -> Scratch {
+        let dir = Scratch::new(name);
+        fs::write(dir.join("input.fa"), fasta).unwrap();
+        dir
+    }
+
+    fn run(dir: &Path, args: &[&str]) -> (Vec<bool>, String) {
+        let input = dir.join("input.fa");
+        let mut argv = vec!["dedupe", "-f", input.to_str().unwrap(), "--dedupe"];
+        argv.extend(args);
+        let options = Command::parse_from(argv).options;
+        let table = dir.join("dedupe.tsv");
+        let keep = dedupe(&options, None, &table).unwrap().unwrap();
+        (keep, fs::read_to_string(table).unwrap())
+    }
+
+    #[test]
+    fn exact_duplicates_keep_the_preferred_record() {
+        let dir = scratch(
+            "dedupe-exact",
+            ">XP_1 predicted\nMKVLAAGIVG\n>NP_1 curated\nmkvlaagivg*\n>XP_2 predicted\nMSTNPKPQRK\n>NP_2 curated\nMSTNPKPQRK\n",
+        );
+
+        let (keep, table) = run(&dir, &[]);
+        assert_eq!(keep, vec![true, false, true, false]);
+        assert!(table.contains("XP_1\tNP_1\tidentical\n"));
+
+        let (keep, table) = run(&dir, &["--dedupe-prefer", "^>NP_"]);
+        assert_eq!(keep, vec![false, true, false, true]);
+        assert_eq!(
+            table,
+            "representative_id\tcollapsed_id\treason\nNP_1\tXP_1\tidentical\nNP_2\tXP_2\tidentical\n"
+        );
+    }
+
+    #[test]
+    fn substrings_collapse_into_the_preferred_representative() {
+        let dir = scratch(
+            "dedupe-substring",
+            ">XP_1\nMSTNPKPQRKTKRNTNRRPQDVKFPGG\n>NP_1\nMSTNPKPQRKTKRNTNRRPQDVKFPGG\n>XP_2\nKPQRKTKRNTNRRPQ\n>XP_3\nMKVLAAGIVGLLLA\n",
+        );
+
+        // Without --dedupe-substrings only the identical pair goes
+        let (keep, _) = run(&dir, &["--dedupe-prefer", "^>NP_"]);
+        assert_eq!(keep, vec![false, true, true, true]);
+
+        let (keep, table) = run(&dir, &["--dedupe-prefer", "^>NP_", "--dedupe-substrings"]);
+        assert_eq!(keep, vec![false, true, false, true]);
+        assert_eq!(
+            table,
+            "representative_id\tcollapsed_id\treason\nNP_1\tXP_1\tidentical\nNP_1\tXP_2\tsubstring\n"
+        );
+    }
+
+    #[test]
+    fn short_sequences_are_never_substrings() {
+        // Anything under SEED residues is only collapsed when identical
+        let dir = scratch(
+            "dedupe-short",
+            ">long\nMSTNPKPQRKTKRNTNRRPQ\n>short\nKPQRKTK\n",
+        );
+        let (keep, _) = run(&dir, &["--dedupe-substrings"]);
+        assert_eq!(keep, vec![true, true]);
+    }
+
+    #[test]
+    fn bad_prefer_patterns_are_refused() {
+        let dir = scratch("dedupe-regex", ">a\nMKV\n");
+        let input = dir.join("input.fa");
+        let options = Command::parse_from([
+            "dedupe",
+            "-f",
+            input.to_str().unwrap(),
+            "--dedupe",
+            "--dedupe-prefer",
+            "(",
+        ])
+        .options;
+        let error = dedupe(&options, None, &dir.join("dedupe.tsv")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
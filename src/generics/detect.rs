@@ -0,0 +1,371 @@
+use super::open_fasta;
+use crate::cli::{DType, MismatchPolicy, OriginType, SplitOptions};
+use colored::Colorize;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::io;
+
+/// What a sample of the input looks like
+#[derive(Debug, Default)]
+pub struct Detected {
+    pub records: usize,
+    pub ensembl: usize,
+    pub ncbi: usize,
+    pub nucleotide: usize,
+    pub uracil: usize,
+    pub ncrna: usize,
+    pub cds_like: usize,
+    pub starts_m: usize,
+    pub ends_stop: usize,
+}
+
+pub fn header_dialect(header: &str) -> OriginType {
+    // >ENSMUSP00000137363.3|ENSMUST00000178446.3|ENSMUSG00000096178.8|...
+    // >ENSP00000354587.3 pep chromosome:GRCh38:MT:1:2:1 gene:ENSG00000198888.2 transcript:...
+    // >lcl|NC_000001.11_prot_NP_001005484.2_1 [gene=OR4F5] [protein_id=NP_001005484.2] ...
+    // >XP_011541469.1 olfactory receptor [Homo sapiens]
+    static RE_ENS_PIPE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^>ENS\w*[PT]\d+(?:\.\d+)?\|[^|]*\|").unwrap());
+    static RE_ENS_SPACE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\sgene:\S+.*\stranscript:\S+").unwrap());
+    static RE_NCBI_TAGS: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\[(?:gene|protein_id|locus_tag|db_xref)=").unwrap());
+    static RE_NCBI_ACC: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^>(?:lcl\|)?[NXYW][CGMPRTW]_\d+").unwrap());
+
+    if RE_ENS_PIPE.is_match(header) || RE_ENS_SPACE.is_match(header) {
+        OriginType::Ensembl
+    } else if RE_NCBI_TAGS.is_match(header) || RE_NCBI_ACC.is_match(header) {
+        OriginType::Ncbi
+    } else {
+        OriginType::Other
+    }
+}
+
+pub fn looks_nucleotide(sequence: &[u8]) -> bool {
+    // Nucleotide if nearly all of it is ACGTUN, protein is
+    // far more varied even where it uses those letters
+    let bases = sequence
+        .iter()
+        .filter(|base| b"ACGTUNacgtun".contains(base))
+        .count();
+    !sequence.is_empty() && bases * 10 >= sequence.len() * 9
+}
+
+impl Detected {
+    pub fn sample(path: &str, limit: usize) -> io::Result<Self> {
+        // Looks at the first limit records only, so it is cheap on any input
+        static RE_ENS_NCRNA: Lazy<Regex> = Lazy::new(|| Regex::new(r"^>\S+ ncrna ").unwrap());
+
+        let mut detected = Detected::default();
+        let mut reader = open_fasta(path)?;
+        for result in reader.records().take(limit) {
+            let record = result?;
+            let header = record.definition().to_string();
+            let sequence = record.sequence().as_ref().to_ascii_uppercase();
+            detected.records += 1;
+
+            match header_dialect(&header) {
+                OriginType::Ensembl => detected.ensembl += 1,
+                OriginType::Ncbi => detected.ncbi += 1,
+                OriginType::Other => {}
+            }
+            if RE_ENS_NCRNA.is_match(&header) {
+                detected.ncrna += 1;
+            }
+
+            if looks_nucleotide(&sequence) {
+                detected.nucleotide += 1;
+                if sequence.contains(&b'U') && !sequence.contains(&b'T') {
+                    detected.uracil += 1;
+                }
+                // A CDS runs start codon to stop codon in whole codons
+                let stop = sequence.len().saturating_sub(3);
+                if sequence.len() % 3 == 0
+                    && sequence.starts_with(b"ATG")
+                    && [&b"TAA"[..], b"TAG", b"TGA"].contains(&&sequence[stop..])
+                {
+                    detected.cds_like += 1;
+                }
+            } else {
+                if sequence.first() == Some(&b'M') {
+                    detected.starts_m += 1;
+                }
+                if sequence.last() == Some(&b'*') {
+                    detected.ends_stop += 1;
+                }
+            }
+        }
+        Ok(detected)
+    }
+
+    fn most(&self, count: usize) -> bool {
+        count * 2 > self.records
+    }
+
+    pub fn origin_db(&self) -> Option<OriginType> {
+        if self.records == 0 {
+            None
+        } else if self.most(self.ensembl) {
+            Some(OriginType::Ensembl)
+        } else if self.most(self.ncbi) {
+            Some(OriginType::Ncbi)
+        } else {
+            Some(OriginType::Other)
+        }
+    }
+
+    pub fn data_type(&self) -> Option<DType> {
+        // CDS and cDNA share an alphabet, so this leans on how many
+        // records run ATG to a stop in whole codons
+        if self.records == 0 {
+            None
+        } else if !self.most(self.nucleotide) {
+            Some(DType::Pep)
+        } else if self.most(self.uracil) || self.most(self.ncrna) {
+            Some(DType::Rna)
+        } else if self.cds_like * 10 >= self.nucleotide * 8 {
+            Some(DType::Cds)
+        } else {
+            Some(DType::Cdna)
+        }
+    }
+
+    pub fn report(&self) {
+        let name = |found: Option<String>| found.unwrap_or("unknown".to_string());
+        println!(
+            "Detected from the first {} records: origin_db={} data_type={}",
+            self.records,
+            name(self.origin_db().map(|found| format!("{:?}", found))),
+            name(self.data_type().map(|found| format!("{:?}", found)))
+        );
+        println!(
+            "  headers: {} ensembl, {} ncbi, {} other",
+            self.ensembl,
+            self.ncbi,
+            self.records - self.ensembl - self.ncbi
+        );
+        if self.data_type() == Some(DType::Pep) {
+            println!(
+                "  sequence: amino acid, {} start with M, {} end with a * stop",
+                self.starts_m, self.ends_stop
+            );
+        } else {
+            println!(
+                "  sequence: {} nucleotide, {} with U rather than T, {} run ATG to a stop in whole codons",
+                self.nucleotide, self.uracil, self.cds_like
+            );
+        }
+    }
+}
+
+fn is_nucleotide(data_type: DType) -> bool {
+    matches!(data_type, DType::Cdna | DType::Cds | DType::Rna)
+}
+
+pub fn detect_types(options: &SplitOptions) -> io::Result<SplitOptions> {
+    // Fill in -d and -r when they weren't given, and check them when
+    // they were. Amino acid vs nucleotide and the header database are
+    // mismatches (--on-mismatch decides), the kind of nucleotide is too
+    // hard to call for sure so it only ever warns
+    let mut options = options.clone();
+    if options.detect_records == 0 {
+        if options.data_type.is_none() || options.origin_db.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--detect-records 0 turns detection off, so -d and -r must both be given",
+            ));
+        }
+        return Ok(options);
+    }
+
+    let detected = Detected::sample(&options.fasta_file, options.detect_records)?;
+    detected.report();
+
+    let mut mismatches: Vec<String> = Vec::new();
+    match (options.data_type, detected.data_type()) {
+        (None, Some(found)) => {
+            println!("Using detected data type: {:?}", found);
+            options.data_type = Some(found);
+        }
+        (None, None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The input has no records to detect a data type from, give one with -d",
+            ))
+        }
+        (Some(DType::Other), _) | (Some(_), None) => {}
+        (Some(given), Some(found)) if given != found => {
+            let message = format!(
+                "-d {:?} was given but the sequence looks like {:?}",
+                given, found
+            );
+            if is_nucleotide(given) && is_nucleotide(found) {
+                println!("{} {}", "WARNING:".yellow(), message);
+            } else {
+                mismatches.push(message);
+            }
+        }
+        _ => {}
+    }
+
+    match (options.origin_db, detected.origin_db()) {
+        (None, found) => {
+            let found = found.unwrap_or(OriginType::Other);
+            println!("Using detected origin database: {:?}", found);
+            options.origin_db = Some(found);
+        }
+        // Other is always allowed, e.g. with a --dialect of your own
+        (Some(OriginType::Other), _) | (Some(_), None) => {}
+        (Some(given), Some(found)) if given != found => mismatches.push(format!(
+            "-r {:?} was given but the headers look like {:?}",
+            given, found
+        )),
+        _ => {}
+    }
+
+    for message in &mismatches {
+        println!("{} {}", "WARNING:".yellow(), message);
+    }
+    if !mismatches.is_empty() && options.on_mismatch == MismatchPolicy::Fail {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}, fix the arguments or use --on-mismatch warn",
+                mismatches.join("; ")
+            ),
+        ));
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::scratch::Scratch;
+    use clap::Parser;
+    use std::fs;
+
+    #[derive(Parser)]
+    struct Command {
+        #[command(flatten)]
+        options: SplitOptions,
+    }
+
+    fn detect(name: &str, fasta: &str, args: &[&str]) -> io::Result<SplitOptions> {
+        let dir = Scratch::new(name);
+        let input = dir.join("input.fa");
+        fs::write(&input, fasta).unwrap();
+        let mut argv = vec!["detect", "-f", input.to_str().unwrap()];
+        argv.extend(args);
+        detect_types(&Command::parse_from(argv).options)
+    }
+
+    const ENSEMBL_PEP: &str = ">ENSP00000354587.3 pep chromosome:GRCh38:MT:3307:4262:1 gene:ENSG00000198888.2 transcript:ENST00000361390.2 gene_biotype:protein_coding\nMPMANLLLLIVPILIAMAFLMLTERKILGYMQLRKGPNVVGPYGLLQPFADAMKLFTKEP\n>ENSP00000355046.4 pep chromosome:GRCh38:MT:4470:5511:1 gene:ENSG00000198763.3 transcript:ENST00000361453.3 gene_biotype:protein_coding\nMNPLAQPVIYSTIFAGTLITALSSHWFFTWVGLEMNMLAFIPVLTKKMNPRSTEAAIKYF\n";
+    const NCBI_CDS: &str = ">lcl|NC_000001.11_cds_NP_001005484.2_1 [gene=OR4F5] [protein_id=NP_001005484.2]\nATGAAGAAGGTAACTGCAGAGGCTATTTCCTGGAATGAATCAACGAGTGAAACGAATTAA\n>lcl|NC_000001.11_cds_XP_011541469.1_2 [gene=OR4F29] [protein_id=XP_011541469.1]\nATGGATGGAGAGAATCACTCAGTGGTATCTGAGTTTTTGTTTCTGGGACTCACTCATTGA\n";
+
+    #[test]
+    fn header_dialects() {
+        for header in [
+            ">ENSP00000354587.3 pep chromosome:GRCh38:MT:3307:4262:1 gene:ENSG00000198888.2 transcript:ENST00000361390.2",
+            ">ENSMUSP00000137363.3|ENSMUST00000178446.3|ENSMUSG00000096178.8|-|-|Gm20837-201|Gm20837|150",
+        ] {
+            assert_eq!(header_dialect(header), OriginType::Ensembl, "{}", header);
+        }
+        for header in [
+            ">lcl|NC_000001.11_prot_NP_001005484.2_1 [gene=OR4F5] [protein_id=NP_001005484.2]",
+            ">XP_011541469.1 olfactory receptor 4F29 [Homo sapiens]",
+            ">NM_001005484.2 Homo sapiens olfactory receptor",
+        ] {
+            assert_eq!(header_dialect(header), OriginType::Ncbi, "{}", header);
+        }
+        for header in [">g1.t1", ">contig_1 some protein", ">ENSG_like gene:x"] {
+            assert_eq!(header_dialect(header), OriginType::Other, "{}", header);
+        }
+    }
+
+    #[test]
+    fn nucleotide_or_protein() {
+        assert!(looks_nucleotide(b"ACGTACGTNNacgu"));
+        // One odd letter in ten is still nucleotide
+        assert!(looks_nucleotide(b"ACGTACGTAR"));
+        assert!(!looks_nucleotide(b"ACGTACGTRR"));
+        assert!(!looks_nucleotide(b"MPMANLLLLIVPILIAMAF"));
+        // Protein made mostly of A, C, G and T is still protein
+        assert!(!looks_nucleotide(b"MACGTAGCTLLKQ"));
+        assert!(!looks_nucleotide(b""));
+    }
+
+    #[test]
+    fn ensembl_peptides_are_detected() {
+        let options = detect("detect-ensembl", ENSEMBL_PEP, &[]).unwrap();
+        assert_eq!(options.data_type, Some(DType::Pep));
+        assert_eq!(options.origin_db, Some(OriginType::Ensembl));
+    }
+
+    #[test]
+    fn ncbi_cds_are_detected() {
+        let options = detect("detect-ncbi", NCBI_CDS, &[]).unwrap();
+        assert_eq!(options.data_type, Some(DType::Cds));
+        assert_eq!(options.origin_db, Some(OriginType::Ncbi));
+    }
+
+    #[test]
+    fn nucleotide_kinds_are_told_apart() {
+        // Not ATG to a stop in whole codons, so cDNA
+        let cdna = ">t1\nGGCATTACGATGCCGTAGCATCGATCGA\n>t2\nCCATGCATCGACTGACGATCGATCG\n";
+        let options = detect("detect-cdna", cdna, &[]).unwrap();
+        assert_eq!(options.data_type, Some(DType::Cdna));
+        assert_eq!(options.origin_db, Some(OriginType::Other));
+
+        let rna = ">r1\nGGCAUUACGAUGCCGUAGCAUCG\n>r2\nCCAUGCAUCGACUGACGAUCG\n";
+        let options = detect("detect-rna", rna, &[]).unwrap();
+        assert_eq!(options.data_type, Some(DType::Rna));
+    }
+
+    #[test]
+    fn given_types_are_checked_against_the_input() {
+        // Amino acid against nucleotide warns by default, or fails
+        let error = detect(
+            "detect-fail",
+            ENSEMBL_PEP,
+            &["-d", "cds", "--on-mismatch", "fail"],
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("-d Cds was given"));
+        let options = detect("detect-warn", ENSEMBL_PEP, &["-d", "cds"]).unwrap();
+        assert_eq!(options.data_type, Some(DType::Cds));
+
+        let error = detect(
+            "detect-origin",
+            NCBI_CDS,
+            &["-r", "ensembl", "--on-mismatch", "fail"],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("-r Ensembl was given"));
+        // -r other is always allowed, and one kind of nucleotide for
+        // another only ever warns
+        let options = detect(
+            "detect-other",
+            NCBI_CDS,
+            &["-r", "other", "-d", "cdna", "--on-mismatch", "fail"],
+        )
+        .unwrap();
+        assert_eq!(options.origin_db, Some(OriginType::Other));
+        assert_eq!(options.data_type, Some(DType::Cdna));
+    }
+
+    #[test]
+    fn detection_can_be_turned_off() {
+        assert!(detect("detect-off", ENSEMBL_PEP, &["--detect-records", "0"]).is_err());
+        let options = detect(
+            "detect-off-given",
+            ENSEMBL_PEP,
+            &["--detect-records", "0", "-d", "cds", "-r", "ncbi"],
+        )
+        .unwrap();
+        assert_eq!(options.data_type, Some(DType::Cds));
+        assert_eq!(options.origin_db, Some(OriginType::Ncbi));
+    }
+}
@@ -0,0 +1,267 @@
+use super::annotation::Annotation;
+use super::header::{Blast, Gff, HeaderFields, HeaderStyle, KeyValue, FIELD_KEYS};
+use crate::cli::{HeaderFormat, SplitOptions};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const DEFAULT_RULES: &str = include_str!("dialects.yaml");
+
+/// The contents of a rules file
+#[derive(Debug, Default, Deserialize)]
+pub struct Rules {
+    pub dialects: BTreeMap<String, DialectRule>,
+}
+
+/// A dialect as written in a rules file
+#[derive(Debug, Clone, Deserialize)]
+pub struct DialectRule {
+    #[serde(default)]
+    pub description: String,
+    pub fields: BTreeMap<String, Vec<String>>,
+    // Only needed for --header-format template
+    #[serde(default)]
+    pub template: Vec<String>,
+}
+
+/// A dialect with its regexes compiled, ready to sanitise headers
+#[derive(Debug)]
+pub struct Dialect {
+    pub name: String,
+    pub description: String,
+    pub format: HeaderFormat,
+    pub annotation: Option<Annotation>,
+    fields: Vec<(String, Vec<Regex>)>,
+    template: Vec<String>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn placeholders(part: &str) -> Vec<&str> {
+    static RE_FIELD: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{(\w+)\}").unwrap());
+    RE_FIELD
+        .captures_iter(part)
+        .map(|caps| caps.get(1).unwrap().as_str())
+        .collect()
+}
+
+impl Rules {
+    pub fn read(path: &str) -> io::Result<Self> {
+        // TOML if the file says so, otherwise YAML
+        let text = fs::read_to_string(path)?;
+        let is_toml = Path::new(path)
+            .extension()
+            .is_some_and(|suffix| suffix == "toml");
+        if is_toml {
+            toml::from_str(&text).map_err(|e| invalid(format!("{}: {}", path, e)))
+        } else {
+            serde_yaml::from_str(&text).map_err(|e| invalid(format!("{}: {}", path, e)))
+        }
+    }
+
+    pub fn with_defaults(rules_file: Option<&str>) -> io::Result<Self> {
+        // Built-in dialects, then any from the user's rules file,
+        // which replace a built-in of the same name
+        let mut rules: Rules = serde_yaml::from_str(DEFAULT_RULES).unwrap();
+        if let Some(path) = rules_file {
+            rules.dialects.extend(Rules::read(path)?.dialects);
+        }
+        Ok(rules)
+    }
+}
+
+impl Dialect {
+    pub fn compile(name: &str, rule: &DialectRule) -> io::Result<Self> {
+        let mut fields = Vec::new();
+        for (field, patterns) in &rule.fields {
+            if !FIELD_KEYS.contains(&field.as_str()) {
+                return Err(invalid(format!(
+                    "Dialect {} has an unknown field {}, expected one of: {}",
+                    name,
+                    field,
+                    FIELD_KEYS.join(", ")
+                )));
+            }
+            let mut regexes = Vec::new();
+            for pattern in patterns {
+                regexes
+                    .push(Regex::new(pattern).map_err(|e| {
+                        invalid(format!("Dialect {} field {}: {}", name, field, e))
+                    })?);
+            }
+            fields.push((field.clone(), regexes));
+        }
+
+        for part in &rule.template {
+            for field in placeholders(part) {
+                if !FIELD_KEYS.contains(&field) {
+                    return Err(invalid(format!(
+                        "Dialect {} template uses an unknown field {{{}}}",
+                        name, field
+                    )));
+                }
+            }
+        }
+
+        Ok(Dialect {
+            name: name.to_string(),
+            description: rule.description.clone(),
+            format: HeaderFormat::Template,
+            annotation: None,
+            fields,
+            template: rule.template.clone(),
+        })
+    }
+
+    pub fn annotation_only() -> io::Result<Self> {
+        // Finds nothing in the header, every field comes from --annotation
+        Dialect::compile(
+            "annotation",
+            &DialectRule {
+                description: "fields from --annotation only".to_string(),
+                fields: BTreeMap::new(),
+                template: Vec::new(),
+            },
+        )
+    }
+
+    pub fn load(name: &str, rules_file: Option<&str>) -> io::Result<Self> {
+        let rules = Rules::with_defaults(rules_file)?;
+        match rules.dialects.get(name) {
+            Some(rule) => Dialect::compile(name, rule),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "No header dialect called {}, the known ones are: {}",
+                    name,
+                    rules
+                        .dialects
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )),
+        }
+    }
+
+    pub fn capture(&self, header: &str) -> HeaderFields {
+        // The value for each field, first matching regex wins
+        let mut found = HeaderFields::default();
+        for (field, regexes) in &self.fields {
+            let value = regexes.iter().find_map(|re| {
+                re.captures(header).map(|caps| {
+                    caps.name(field)
+                        .unwrap_or_else(|| caps.get(0).unwrap())
+                        .as_str()
+                        .to_string()
+                })
+            });
+            if let Some(value) = value {
+                found.set(field, value);
+            }
+        }
+        found
+    }
+
+    fn annotated(&self, header: &str, count: bool) -> HeaderFields {
+        // The header wins, the annotation fills in what it is missing
+        let mut found = self.capture(header);
+        if let Some(annotation) = &self.annotation {
+            let entry = annotation.lookup(header);
+            if count {
+                annotation.count(entry.is_some());
+            }
+            if let Some(entry) = entry {
+                found.fill_from(entry);
+            }
+        }
+        found
+    }
+
+    pub fn fields(&self, header: &str) -> HeaderFields {
+        self.annotated(header, false)
+    }
+
+    pub fn record_fields(&self, header: &str) -> HeaderFields {
+        // As fields, for a record being written, so it counts towards
+        // the annotation report
+        self.annotated(header, true)
+    }
+
+    fn serialise(&self, found: &HeaderFields) -> String {
+        match self.format {
+            HeaderFormat::Template => self.fill_template(found),
+            HeaderFormat::KeyValue => KeyValue.serialise(found),
+            HeaderFormat::Gff => Gff.serialise(found),
+            HeaderFormat::Blast => Blast.serialise(found),
+        }
+    }
+
+    pub fn sanitise(&self, header: &str) -> String {
+        self.serialise(&self.fields(header))
+    }
+
+    pub fn sanitise_record(&self, header: &str) -> String {
+        self.serialise(&self.record_fields(header))
+    }
+
+    fn fill_template(&self, found: &HeaderFields) -> String {
+        // Skipping parts with a missing field
+        let mut new_header = String::new();
+        for part in &self.template {
+            let fields = placeholders(part);
+            if fields.iter().any(|field| found.get(field).is_none()) {
+                continue;
+            }
+            let mut filled = part.clone();
+            for field in fields {
+                filled = filled.replace(&format!("{{{}}}", field), found.get(field).unwrap());
+            }
+            new_header.push_str(&filled);
+        }
+        new_header
+    }
+}
+
+pub fn load_sanitiser(options: &SplitOptions) -> io::Result<Option<Dialect>> {
+    // The dialect --clean_headers will use, None if headers are left alone
+    if !options.sanitise {
+        return Ok(None);
+    }
+    let mut dialect = match (options.dialect_name(), &options.annotation) {
+        (Some(name), _) => Dialect::load(&name, options.rules.as_deref())?,
+        // Bare ID headers need no dialect when the annotation has the fields
+        (None, Some(_)) => Dialect::annotation_only()?,
+        (None, None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "-r other has no built-in header dialect, pick one with --dialect (and --rules for your own) or give an --annotation",
+            ))
+        }
+    };
+    dialect.format = options.header_format;
+    if dialect.format == HeaderFormat::Template && dialect.template.is_empty() {
+        if options.dialect_name().is_some() {
+            return Err(invalid(format!(
+                "Dialect {} has no template, pick another --header-format",
+                dialect.name
+            )));
+        }
+        dialect.format = HeaderFormat::KeyValue;
+    }
+    println!(
+        "Sanitising headers with the {} dialect: {}",
+        dialect.name, dialect.description
+    );
+    if let Some(path) = &options.annotation {
+        dialect.annotation = Some(Annotation::load(path, options.with_location)?);
+    }
+    Ok(Some(dialect))
+}
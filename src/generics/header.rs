@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+/// The parts of a FASTA header the sanitisers understand
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderFields {
+    pub protein: Option<String>,
+    pub gene: Option<String>,
+    pub transcript: Option<String>,
+    pub symbol: Option<String>,
+    pub biotype: Option<String>,
+    pub source: Option<String>,
+    pub location: Option<String>,
+}
+
+// The names used for each field in dialects and in key=value headers,
+// in the order they are written
+pub const FIELD_KEYS: [&str; 7] = [
+    "protein_id",
+    "gene_id",
+    "transcript_id",
+    "biotype",
+    "source",
+    "location",
+    "gene_name",
+];
+
+impl HeaderFields {
+    pub fn get(&self, key: &str) -> Option<&String> {
+        match key {
+            "protein_id" => self.protein.as_ref(),
+            "gene_id" => self.gene.as_ref(),
+            "transcript_id" => self.transcript.as_ref(),
+            "gene_name" => self.symbol.as_ref(),
+            "biotype" => self.biotype.as_ref(),
+            "source" => self.source.as_ref(),
+            "location" => self.location.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: String) {
+        match key {
+            "protein_id" => self.protein = Some(value),
+            "gene_id" => self.gene = Some(value),
+            "transcript_id" => self.transcript = Some(value),
+            "gene_name" => self.symbol = Some(value),
+            "biotype" => self.biotype = Some(value),
+            "source" => self.source = Some(value),
+            "location" => self.location = Some(value),
+            _ => {}
+        }
+    }
+
+    pub fn fill_from(&mut self, other: &HeaderFields) {
+        // Take any field this is missing from other
+        for key in FIELD_KEYS {
+            if self.get(key).is_none() {
+                if let Some(value) = other.get(key) {
+                    self.set(key, value.clone());
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        FIELD_KEYS.iter().all(|key| self.get(key).is_none())
+    }
+}
+
+/// Turns HeaderFields into a header and back again
+pub trait HeaderStyle {
+    fn serialise(&self, fields: &HeaderFields) -> String;
+    fn parse(&self, header: &str) -> HeaderFields;
+}
+
+/// protein_id=ENSP...;gene_id=ENSG...;transcript_id=ENST...;gene_name=ABC
+/// the style the sanitisers have always written
+pub struct KeyValue;
+
+impl HeaderStyle for KeyValue {
+    fn serialise(&self, fields: &HeaderFields) -> String {
+        // Every part ends in ; apart from gene_name
+        let mut header = String::new();
+        for key in FIELD_KEYS {
+            if let Some(value) = fields.get(key) {
+                header.push_str(&format!("{}={}", key, value));
+                if key != "gene_name" {
+                    header.push(';');
+                }
+            }
+        }
+        header
+    }
+
+    fn parse(&self, header: &str) -> HeaderFields {
+        let mut fields = HeaderFields::default();
+        for part in header.trim_start_matches('>').split(';') {
+            if let Some((key, value)) = part.split_once('=') {
+                fields.set(key.trim(), value.to_string());
+            }
+        }
+        fields
+    }
+}
+
+/// ID=ENSP...;gene_id=ENSG...;transcript_id=ENST...;Name=ABC
+/// as in column 9 of a GFF3, with the reserved characters escaped
+pub struct Gff;
+
+fn gff_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            ';' | '=' | '&' | ',' | '%' | '\t' | ' ' => {
+                escaped.push_str(&format!("%{:02X}", c as u32))
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn gff_unescape(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            let code: String = chars.by_ref().take(2).collect();
+            match u8::from_str_radix(&code, 16) {
+                Ok(byte) => unescaped.push(byte as char),
+                Err(_) => {
+                    unescaped.push('%');
+                    unescaped.push_str(&code);
+                }
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+impl HeaderStyle for Gff {
+    fn serialise(&self, fields: &HeaderFields) -> String {
+        // ID is the most specific ID there is
+        let mut attributes: Vec<(&str, &String)> = Vec::new();
+        if let Some(id) = fields
+            .protein
+            .as_ref()
+            .or(fields.transcript.as_ref())
+            .or(fields.gene.as_ref())
+        {
+            attributes.push(("ID", id));
+        }
+        for key in FIELD_KEYS {
+            if let Some(value) = fields.get(key) {
+                let key = if key == "gene_name" { "Name" } else { key };
+                attributes.push((key, value));
+            }
+        }
+        attributes
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, gff_escape(value)))
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+
+    fn parse(&self, header: &str) -> HeaderFields {
+        let attributes: BTreeMap<&str, String> = header
+            .trim_start_matches('>')
+            .split(';')
+            .filter_map(|part| part.split_once('='))
+            .map(|(key, value)| (key.trim(), gff_unescape(value)))
+            .collect();
+        let mut fields = HeaderFields::default();
+        for (key, value) in attributes {
+            let key = if key == "Name" { "gene_name" } else { key };
+            fields.set(key, value);
+        }
+        fields
+    }
+}
+
+/// ENSP00000354587.3__ENSG00000198888.2__ENST00000361390.2__protein_coding______
+/// one token with no spaces or pipes, which BLAST and most aligners take
+/// whole. Fields are always all there, in FIELD_KEYS order, empty if missing
+pub struct Blast;
+
+const BLAST_SEPARATOR: &str = "__";
+
+fn blast_safe(value: &str) -> String {
+    // Anything outside letters, digits and ._- becomes -, and a run of
+    // underscores is cut to one so it can't be read as a separator
+    let mut safe = String::new();
+    for c in value.chars() {
+        let c = if c.is_ascii_alphanumeric() || "._-".contains(c) {
+            c
+        } else {
+            '-'
+        };
+        if !(c == '_' && safe.ends_with('_')) {
+            safe.push(c);
+        }
+    }
+    safe.trim_matches('_').to_string()
+}
+
+impl HeaderStyle for Blast {
+    fn serialise(&self, fields: &HeaderFields) -> String {
+        if fields.is_empty() {
+            return String::new();
+        }
+        FIELD_KEYS
+            .iter()
+            .map(|key| {
+                fields
+                    .get(key)
+                    .map(|value| blast_safe(value))
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<String>>()
+            .join(BLAST_SEPARATOR)
+    }
+
+    fn parse(&self, header: &str) -> HeaderFields {
+        let mut fields = HeaderFields::default();
+        let values = header.trim_start_matches('>').split(BLAST_SEPARATOR);
+        for (key, value) in FIELD_KEYS.iter().zip(values) {
+            if !value.is_empty() {
+                fields.set(key, value.to_string());
+            }
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ensembl_fields() -> HeaderFields {
+        HeaderFields {
+            protein: Some("ENSP00000354587.3".to_string()),
+            gene: Some("ENSG00000198888.2".to_string()),
+            transcript: Some("ENST00000361390.2".to_string()),
+            symbol: Some("MT-ND1".to_string()),
+            biotype: Some("protein_coding".to_string()),
+            source: Some("ensembl".to_string()),
+            location: Some("chromosome.GRCh38.MT.3307.4262.1".to_string()),
+        }
+    }
+
+    fn partial_fields() -> HeaderFields {
+        HeaderFields {
+            protein: Some("XP_011520339.1".to_string()),
+            gene: Some("GeneID.100287102".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn key_value_round_trips() {
+        for fields in [ensembl_fields(), partial_fields()] {
+            let header = KeyValue.serialise(&fields);
+            assert_eq!(KeyValue.parse(&header), fields, "{}", header);
+        }
+        assert_eq!(
+            KeyValue.serialise(&partial_fields()),
+            "protein_id=XP_011520339.1;gene_id=GeneID.100287102;"
+        );
+        assert!(KeyValue
+            .serialise(&ensembl_fields())
+            .ends_with(";gene_name=MT-ND1"));
+    }
+
+    #[test]
+    fn gff_round_trips_with_escaped_values() {
+        let mut fields = ensembl_fields();
+        fields.symbol = Some("NADH dehydrogenase; subunit=1, 100%".to_string());
+        let header = Gff.serialise(&fields);
+        assert!(header.starts_with("ID=ENSP00000354587.3;protein_id=ENSP00000354587.3;"));
+        assert!(header.contains("Name=NADH%20dehydrogenase%3B%20subunit%3D1%2C%20100%25"));
+        assert_eq!(Gff.parse(&header), fields);
+        assert_eq!(Gff.parse(&format!(">{}", header)), fields);
+
+        let fields = partial_fields();
+        let header = Gff.serialise(&fields);
+        assert!(header.starts_with("ID=XP_011520339.1;"));
+        assert_eq!(Gff.parse(&header), fields);
+    }
+
+    #[test]
+    fn blast_round_trips() {
+        for fields in [ensembl_fields(), partial_fields()] {
+            let header = Blast.serialise(&fields);
+            assert!(!header.contains(' ') && !header.contains('|'));
+            assert_eq!(Blast.parse(&header), fields, "{}", header);
+        }
+        // Every field has a slot, empty ones included
+        assert_eq!(
+            Blast.serialise(&partial_fields()),
+            "XP_011520339.1__GeneID.100287102__________"
+        );
+        assert_eq!(Blast.serialise(&HeaderFields::default()), "");
+    }
+
+    #[test]
+    fn blast_makes_values_safe() {
+        let fields = HeaderFields {
+            protein: Some("sp|P12345|ABC_HUMAN".to_string()),
+            location: Some("chr1:100-200".to_string()),
+            ..Default::default()
+        };
+        let parsed = Blast.parse(&Blast.serialise(&fields));
+        assert_eq!(parsed.protein.as_deref(), Some("sp-P12345-ABC_HUMAN"));
+        assert_eq!(parsed.location.as_deref(), Some("chr1-100-200"));
+    }
+}
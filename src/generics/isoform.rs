@@ -0,0 +1,263 @@
+use super::annotation::Annotation;
+use super::dialect::Dialect;
+use super::open_fasta;
+use crate::cli::SplitOptions;
+use colored::Colorize;
+use noodles::fasta::Record;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io;
+
+struct Representative {
+    index: usize,
+    length: usize,
+    tagged: bool,
+}
+
+fn gene_dialect(options: &SplitOptions) -> io::Result<Dialect> {
+    // The same dialect and annotation --clean_headers reads, the gene a
+    // record belongs to is its gene_id field
+    let mut dialect = match (options.dialect_name(), &options.annotation) {
+        (Some(name), _) => Dialect::load(&name, options.rules.as_deref())?,
+        (None, Some(_)) => Dialect::annotation_only()?,
+        (None, None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--longest-isoform finds each record's gene with a header dialect, pick one with --dialect (and --rules for your own) or give an --annotation",
+            ))
+        }
+    };
+    if let Some(path) = &options.annotation {
+        dialect.annotation = Some(Annotation::load(path, false)?);
+    }
+    Ok(dialect)
+}
+
+pub fn is_canonical(header: &str) -> bool {
+    // Ensembl marks its canonical transcript and both databases mark
+    // MANE Select, when the header carries either it beats length
+    static RE_TAG: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?i)ensembl_canonical|mane[_ ]select").unwrap());
+    RE_TAG.is_match(header)
+}
+
+pub fn longest_isoforms(options: &SplitOptions, qc: &Option<Vec<bool>>) -> io::Result<Vec<bool>> {
+    // First pass over the input, returns which records to keep by index.
+    // One record per gene: a canonical/MANE tagged one if there is one,
+    // otherwise the longest, ties going to the first in the input.
+    // Records with no gene ID found are all kept. Records QC removed
+    // can't stand for their gene
+    let dialect = gene_dialect(options)?;
+    let mut reader = open_fasta(&options.fasta_file)?;
+    let mut keep: Vec<bool> = Vec::new();
+    let mut genes: HashMap<String, Representative> = HashMap::new();
+    for (index, result) in reader.records().enumerate() {
+        let record = result?;
+        if qc.as_ref().is_some_and(|qc| qc.get(index) != Some(&true)) {
+            keep.push(false);
+            continue;
+        }
+        let header = record.definition().to_string();
+        let candidate = Representative {
+            index,
+            length: record.sequence().len(),
+            tagged: is_canonical(&header),
+        };
+
+        let Some(gene) = dialect.fields(&header).gene else {
+            keep.push(true);
+            continue;
+        };
+        keep.push(false);
+        match genes.get_mut(&gene) {
+            Some(best) => {
+                if (candidate.tagged, candidate.length) > (best.tagged, best.length) {
+                    *best = candidate;
+                }
+            }
+            None => {
+                genes.insert(gene, candidate);
+            }
+        }
+    }
+
+    if genes.is_empty() {
+        println!(
+            "{} --longest-isoform found no gene_id with the {} dialect, every record is kept",
+            "WARNING:".yellow(),
+            dialect.name
+        );
+    }
+    let tagged = genes.values().filter(|best| best.tagged).count();
+    for best in genes.values() {
+        keep[best.index] = true;
+    }
+    println!(
+        "Longest isoform: keeping {} of {} records, one for each of {} genes ({} canonical/MANE tagged)",
+        keep.iter().filter(|kept| **kept).count(),
+        keep.len(),
+        genes.len(),
+        tagged
+    );
+    Ok(keep)
+}
+
+pub fn kept_records<'a>(
+    records: impl Iterator<Item = io::Result<Record>> + 'a,
+    keep: &'a Option<Vec<bool>>,
+) -> impl Iterator<Item = io::Result<Record>> + 'a {
+    // Drop the records QC or --longest-isoform filtered out, when set
+    records
+        .enumerate()
+        .filter(move |(index, _)| {
+            keep.as_ref()
+                .is_none_or(|keep| keep.get(*index) == Some(&true))
+        })
+        .map(|(_, result)| result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::scratch::Scratch;
+    use clap::Parser;
+    use std::fs;
+
+    #[derive(Parser)]
+    struct Command {
+        #[command(flatten)]
+        options: SplitOptions,
+    }
+
+    fn longest(name: &str, fasta: &str, qc: &Option<Vec<bool>>) -> Vec<bool> {
+        let dir = Scratch::new(name);
+        let input = dir.join("input.fa");
+        fs::write(&input, fasta).unwrap();
+        let options = Command::parse_from([
+            "isoform",
+            "-f",
+            input.to_str().unwrap(),
+            "-r",
+            "ensembl",
+            "--longest-isoform",
+        ])
+        .options;
+        longest_isoforms(&options, qc).unwrap()
+    }
+
+    fn record(protein: &str, gene: &str, tags: &str, sequence: &str) -> String {
+        format!(
+            ">{} pep chromosome:GRCh38:1:1:100:1 gene:{} transcript:{}{}\n{}\n",
+            protein,
+            gene,
+            protein.replace("ENSP", "ENST"),
+            tags,
+            sequence
+        )
+    }
+
+    #[test]
+    fn canonical_tags() {
+        assert!(is_canonical(
+            ">ENSP1 gene:ENSG1 transcript:ENST1 Ensembl_canonical"
+        ));
+        assert!(is_canonical(">NP_1 [tag=MANE Select]"));
+        assert!(is_canonical(">ENSP1 tag:mane_select"));
+        assert!(!is_canonical(">ENSP1 gene:ENSG1 transcript:ENST1"));
+        assert!(!is_canonical(">NP_1 MANE Plus Clinical"));
+    }
+
+    #[test]
+    fn longest_isoform_per_gene() {
+        let fasta = [
+            record("ENSP1", "ENSG1", "", "MKV"),
+            record("ENSP2", "ENSG1", "", "MKVLAAG"),
+            record("ENSP3", "ENSG2", "", "MSTNP"),
+            record("ENSP4", "ENSG1", "", "MKVL"),
+        ]
+        .concat();
+        assert_eq!(
+            longest("isoform-longest", &fasta, &None),
+            vec![false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn length_ties_go_to_the_first_record() {
+        let fasta = [
+            record("ENSP1", "ENSG1", "", "MKVL"),
+            record("ENSP2", "ENSG1", "", "MSTN"),
+            record("ENSP3", "ENSG1", "", "MKV"),
+        ]
+        .concat();
+        assert_eq!(
+            longest("isoform-tie", &fasta, &None),
+            vec![true, false, false]
+        );
+    }
+
+    #[test]
+    fn tagged_records_beat_longer_ones() {
+        let fasta = [
+            record("ENSP1", "ENSG1", "", "MKVLAAGIVG"),
+            record("ENSP2", "ENSG1", " Ensembl_canonical", "MKV"),
+            record("ENSP3", "ENSG2", "", "MSTNPKPQRK"),
+            record("ENSP4", "ENSG2", " MANE_Select", "MSTN"),
+            record("ENSP5", "ENSG2", " MANE_Select", "MSTNP"),
+        ]
+        .concat();
+        assert_eq!(
+            longest("isoform-tagged", &fasta, &None),
+            vec![false, true, false, false, true]
+        );
+    }
+
+    #[test]
+    fn records_with_no_gene_are_all_kept() {
+        let fasta = [
+            record("ENSP1", "ENSG1", "", "MKV"),
+            ">orphan_1 no gene here\nMKVLAAG\n".to_string(),
+            record("ENSP2", "ENSG1", "", "MKVL"),
+            ">orphan_2 nor here\nM\n".to_string(),
+        ]
+        .concat();
+        assert_eq!(
+            longest("isoform-orphans", &fasta, &None),
+            vec![false, true, true, true]
+        );
+    }
+
+    #[test]
+    fn qc_removed_records_cannot_stand_for_their_gene() {
+        let fasta = [
+            record("ENSP1", "ENSG1", "", "MKV"),
+            record("ENSP2", "ENSG1", " Ensembl_canonical", "MKVLAAG"),
+            record("ENSP3", "ENSG1", "", "MKVL"),
+            ">orphan_1\nMSTN\n".to_string(),
+        ]
+        .concat();
+        let qc = Some(vec![true, false, true, false]);
+        assert_eq!(
+            longest("isoform-qc", &fasta, &qc),
+            vec![false, false, true, false]
+        );
+    }
+
+    #[test]
+    fn kept_records_filters_by_index() {
+        let dir = Scratch::new("isoform-kept");
+        let input = dir.join("input.fa");
+        fs::write(&input, ">a\nM\n>b\nMK\n>c\nMKV\n").unwrap();
+        let names = |keep: &Option<Vec<bool>>| -> Vec<String> {
+            let mut reader = open_fasta(input.to_str().unwrap()).unwrap();
+            kept_records(reader.records(), keep)
+                .map(|record| String::from_utf8_lossy(record.unwrap().name()).to_string())
+                .collect()
+        };
+        assert_eq!(names(&None), vec!["a", "b", "c"]);
+        assert_eq!(names(&Some(vec![true, false, true])), vec!["a", "c"]);
+        // Records past the end of the mask are dropped
+        assert_eq!(names(&Some(vec![false, true])), vec!["b"]);
+    }
+}
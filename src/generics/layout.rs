@@ -0,0 +1,505 @@
+use crate::cli::SplitOptions;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+// The folders a split writes under -o, which is normally the
+// {root}/{clade}/{organism} folder of the geneset
+pub const SPLIT_LAYOUT: &str = "{accession}/{type}";
+// The same folders from the geneset root, what gencsv and prepare-batch use
+pub const GENESET_LAYOUT: &str = "{clade}/{organism}/{accession}/{type}";
+pub const COUNT_FILE_NAME: &str = "{accession}_f{index}_c{size}.fa";
+pub const SIZE_FILE_NAME: &str = "{accession}_f{index}_{type}.fasta";
+pub const GROUP_FILE_NAME: &str = "{accession}_{group}.fa";
+
+// Placeholders a folder can be named with, file names can also use
+// the chunk number, the records in the chunk and the -s of the split
+pub const FOLDER_FIELDS: [&str; 4] = ["clade", "organism", "accession", "type"];
+const FILE_FIELDS: [&str; 7] = [
+    "clade",
+    "organism",
+    "accession",
+    "type",
+    "index",
+    "count",
+    "size",
+];
+
+// Suffixes taken off the input file name, in any order and any case
+const COMPRESSION_SUFFIXES: [&str; 6] = ["gz", "bgz", "bz2", "xz", "zst", "zip"];
+const FASTA_SUFFIXES: [&str; 13] = [
+    "fa", "fasta", "fas", "fna", "faa", "ffn", "frn", "fsa", "mpfa", "seq", "aa", "nt", "txt",
+];
+// Data type tokens at the end of an Ensembl name (Homo_sapiens.GRCh38.pep.all)
+// and NCBI one (GCF_000001405.40_GRCh38.p14_protein), which say what is in
+// the file rather than which assembly it is from
+const ENSEMBL_TOKENS: [&str; 14] = [
+    "all",
+    "abinitio",
+    "toplevel",
+    "primary_assembly",
+    "nonchromosomal",
+    "pep",
+    "cdna",
+    "cds",
+    "ncrna",
+    "rna",
+    "dna",
+    "dna_sm",
+    "dna_rm",
+    "protein",
+];
+const NCBI_SUFFIXES: [&str; 6] = [
+    "_cds_from_genomic",
+    "_rna_from_genomic",
+    "_translated_cds",
+    "_protein",
+    "_rna",
+    "_genomic",
+];
+
+pub fn base_name(fasta_file: &str) -> String {
+    // The name an input's output is filed under when --accession isn't
+    // given. Compression and FASTA suffixes come off, then any data type
+    // tokens, but everything else is kept, so assembly accessions
+    // (GCA_000001405.29), assembly names (GRCh38.p14) and Ensembl
+    // releases (.113) keep different inputs apart
+    let file_name = Path::new(fasta_file)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| fasta_file.to_string());
+    let mut tokens: Vec<&str> = file_name.split('.').collect();
+    let strip = |tokens: &mut Vec<&str>, known: &[&str]| {
+        // Always leave the first token, a file called pep.fa is still "pep"
+        while tokens.len() > 1 && known.contains(&tokens.last().unwrap().to_lowercase().as_str()) {
+            tokens.pop();
+        }
+    };
+    strip(
+        &mut tokens,
+        &[&COMPRESSION_SUFFIXES[..], &FASTA_SUFFIXES[..]].concat(),
+    );
+    strip(&mut tokens, &ENSEMBL_TOKENS);
+
+    let mut name = tokens.join(".");
+    if let Some(suffix) = NCBI_SUFFIXES
+        .iter()
+        .find(|suffix| name.len() > suffix.len() && name.to_lowercase().ends_with(*suffix))
+    {
+        name.truncate(name.len() - suffix.len());
+    }
+    name
+}
+
+pub fn output_name(options: &SplitOptions) -> String {
+    // --accession if it was given, otherwise worked out from the input
+    options
+        .accession
+        .clone()
+        .unwrap_or_else(|| base_name(&options.fasta_file))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+/// A path with {placeholder}s in it, which can be filled in to make a
+/// path or matched against a path to read the placeholders back out
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub text: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn new(text: &str, allowed: &[&str], what: &str) -> io::Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let Some(end) = rest[start..].find('}') else {
+                return Err(invalid(format!("{} {}: no closing }}", what, text)));
+            };
+            let field = &rest[start + 1..start + end];
+            if !allowed.contains(&field) {
+                return Err(invalid(format!(
+                    "{} {}: no placeholder {{{}}}, the ones that can be used are {}",
+                    what,
+                    text,
+                    field,
+                    allowed
+                        .iter()
+                        .map(|field| format!("{{{}}}", field))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )));
+            }
+            parts.push(Part::Field(field.to_string()));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        if parts
+            .iter()
+            .any(|part| matches!(part, Part::Text(text) if text.contains('}')))
+        {
+            return Err(invalid(format!("{} {}: no opening {{", what, text)));
+        }
+        if text.starts_with('/')
+            || text
+                .split('/')
+                .any(|folder| folder.is_empty() || folder == "." || folder == "..")
+        {
+            return Err(invalid(format!(
+                "{} {}: has to be a relative path without empty, . or .. folders",
+                what, text
+            )));
+        }
+        Ok(Template {
+            text: text.to_string(),
+            parts,
+        })
+    }
+
+    pub fn uses(&self, field: &str) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field(name) if name == field))
+    }
+
+    pub fn depth(&self) -> usize {
+        self.text.split('/').count()
+    }
+
+    pub fn render(&self, values: &HashMap<&str, String>) -> io::Result<String> {
+        let mut path = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => path.push_str(text),
+                Part::Field(field) => match values.get(field.as_str()) {
+                    Some(value) if !value.is_empty() && !value.contains('/') => {
+                        path.push_str(value)
+                    }
+                    Some(value) => {
+                        return Err(invalid(format!(
+                            "{{{}}} can't be {:?} in {}",
+                            field, value, self.text
+                        )))
+                    }
+                    None => {
+                        return Err(invalid(format!(
+                            "{} needs a value for {{{}}}, e.g. --{}",
+                            self.text, field, field
+                        )))
+                    }
+                },
+            }
+        }
+        Ok(path)
+    }
+
+    pub fn parse(&self, path: &str) -> Option<HashMap<String, String>> {
+        // The placeholders in a path made by render, None if it doesn't
+        // fit. A placeholder used twice has to have the same value both times
+        let pattern: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => regex::escape(text),
+                Part::Field(_) => "([^/]+?)".to_string(),
+            })
+            .collect();
+        let captures = Regex::new(&format!("^{}$", pattern)).ok()?.captures(path)?;
+        let mut values = HashMap::new();
+        let fields = self.parts.iter().filter_map(|part| match part {
+            Part::Field(field) => Some(field),
+            Part::Text(_) => None,
+        });
+        for (field, value) in fields.zip(captures.iter().skip(1)) {
+            let value = value?.as_str().to_string();
+            if values.get(field).is_some_and(|seen| *seen != value) {
+                return None;
+            }
+            values.insert(field.clone(), value);
+        }
+        Some(values)
+    }
+}
+
+/// Where one split writes, the data folder from --layout, the chunk
+/// names from --file-name and the files kept next to the data folder
+#[derive(Debug)]
+pub struct Layout {
+    pub outdir: String,
+    sidecar: String,
+    file: Template,
+    values: HashMap<&'static str, String>,
+}
+
+impl Layout {
+    pub fn new(
+        options: &SplitOptions,
+        accession: &str,
+        data_type: &str,
+        size: usize,
+        default_file: &str,
+    ) -> io::Result<Self> {
+        let dir = Template::new(&options.layout, &FOLDER_FIELDS, "--layout")?;
+        let file = Template::new(
+            options.file_name.as_deref().unwrap_or(default_file),
+            &FILE_FIELDS,
+            "--file-name",
+        )?;
+        if file.depth() > 1 {
+            return Err(invalid(format!(
+                "--file-name {} can't have folders in it, they go in --layout",
+                file.text
+            )));
+        }
+        if !file.uses("index") {
+            return Err(invalid(format!(
+                "--file-name {} needs {{index}}, or every chunk would have the same name",
+                file.text
+            )));
+        }
+
+        let mut values = HashMap::from([
+            ("accession", accession.to_string()),
+            ("type", data_type.to_string()),
+            ("size", size.to_string()),
+        ]);
+        for (field, value) in [("clade", &options.clade), ("organism", &options.organism)] {
+            if let Some(value) = value {
+                values.insert(field, value.clone());
+            }
+        }
+        // Check the file name fills in before anything is written
+        let mut sample = values.clone();
+        sample.insert("index", "1".to_string());
+        sample.insert("count", "1".to_string());
+        file.render(&sample)?;
+
+        for (field, value) in &values {
+            if value.starts_with('.') {
+                return Err(invalid(format!(
+                    "{{{}}} can't be {:?}, files starting with . are hidden and skipped by gencsv",
+                    field, value
+                )));
+            }
+        }
+
+        // Manifests, rename maps and reports sit beside the data folder,
+        // named after it, so gencsv never sees them as data
+        let folder = dir.render(&values)?;
+        // gencsv has to read the same values back out of the path, which
+        // it can't if say {clade}-{accession} is given a clade with a - in it
+        let parsed = dir.parse(&folder).unwrap_or_default();
+        for (field, value) in &values {
+            if dir.uses(field) && parsed.get(*field) != Some(value) {
+                return Err(invalid(format!(
+                    "{} would be read back by gencsv with {{{}}} as {:?} rather than {:?}, change the value or put the placeholders in separate folders",
+                    folder,
+                    field,
+                    parsed.get(*field).map(String::as_str).unwrap_or_default(),
+                    value
+                )));
+            }
+        }
+        let sidecar = format!("{}/{}", options.outpath, folder);
+        Ok(Layout {
+            outdir: format!("{}/", sidecar),
+            sidecar,
+            file,
+            values,
+        })
+    }
+
+    pub fn set_size(&mut self, size: usize) {
+        // For a {size} only known once the input has been counted
+        self.values.insert("size", size.to_string());
+    }
+
+    pub fn file_name(&self, index: usize, count: usize) -> String {
+        let mut values = self.values.clone();
+        values.insert("index", index.to_string());
+        values.insert("count", count.to_string());
+        self.file.render(&values).expect("checked in Layout::new")
+    }
+
+    pub fn sidecar(&self, suffix: &str) -> String {
+        format!("{}_{}", self.sidecar, suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&'static str, &str)]) -> HashMap<&'static str, String> {
+        pairs
+            .iter()
+            .map(|(field, value)| (*field, value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn render_then_parse_gives_the_values_back() {
+        let template = Template::new(GENESET_LAYOUT, &FOLDER_FIELDS, "--layout").unwrap();
+        let path = template
+            .render(&values(&[
+                ("clade", "insects"),
+                ("organism", "ApisMellifera"),
+                ("accession", "GCA_003254395.2"),
+                ("type", "pep"),
+            ]))
+            .unwrap();
+        assert_eq!(path, "insects/ApisMellifera/GCA_003254395.2/pep");
+
+        let parsed = template.parse(&path).unwrap();
+        assert_eq!(parsed["clade"], "insects");
+        assert_eq!(parsed["organism"], "ApisMellifera");
+        assert_eq!(parsed["accession"], "GCA_003254395.2");
+        assert_eq!(parsed["type"], "pep");
+        assert_eq!(template.depth(), 4);
+    }
+
+    #[test]
+    fn file_names_round_trip() {
+        let template = Template::new(COUNT_FILE_NAME, &FILE_FIELDS, "--file-name").unwrap();
+        let path = template
+            .render(&values(&[
+                ("accession", "GCA_003254395.2"),
+                ("index", "12"),
+                ("size", "500"),
+            ]))
+            .unwrap();
+        assert_eq!(path, "GCA_003254395.2_f12_c500.fa");
+        let parsed = template.parse(&path).unwrap();
+        assert_eq!(parsed["accession"], "GCA_003254395.2");
+        assert_eq!(parsed["index"], "12");
+        assert_eq!(parsed["size"], "500");
+    }
+
+    #[test]
+    fn parse_refuses_paths_that_dont_fit() {
+        let template = Template::new(SPLIT_LAYOUT, &FOLDER_FIELDS, "--layout").unwrap();
+        assert!(template.parse("GCA_003254395.2").is_none());
+        assert!(template.parse("GCA_003254395.2/pep/extra").is_none());
+        // A placeholder used twice has to match itself
+        let template =
+            Template::new("{type}/{accession}_{type}", &FOLDER_FIELDS, "--layout").unwrap();
+        assert!(template.parse("pep/GCA1.2_pep").is_some());
+        assert!(template.parse("pep/GCA1.2_cdna").is_none());
+    }
+
+    #[test]
+    fn new_refuses_unbalanced_braces() {
+        for text in [
+            "{accession/{type}",
+            "{accession}/type}",
+            "{accession}/{type",
+        ] {
+            let error = Template::new(text, &FOLDER_FIELDS, "--layout").unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", text);
+        }
+        assert!(
+            Template::new("{accession}/{type", &FOLDER_FIELDS, "--layout")
+                .unwrap_err()
+                .to_string()
+                .contains("no closing }")
+        );
+        assert!(
+            Template::new("{accession}/type}", &FOLDER_FIELDS, "--layout")
+                .unwrap_err()
+                .to_string()
+                .contains("no opening {")
+        );
+    }
+
+    #[test]
+    fn new_refuses_paths_leaving_the_output_folder() {
+        for text in [
+            "../{accession}/{type}",
+            "{accession}/../{type}",
+            "{accession}/./{type}",
+            "{accession}//{type}",
+            "/{accession}/{type}",
+        ] {
+            assert!(
+                Template::new(text, &FOLDER_FIELDS, "--layout").is_err(),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn new_refuses_unknown_placeholders() {
+        // {index} is only for file names
+        assert!(Template::new("{accession}/{index}", &FOLDER_FIELDS, "--layout").is_err());
+        assert!(Template::new("{accession}_{index}.fa", &FILE_FIELDS, "--file-name").is_ok());
+    }
+
+    #[test]
+    fn render_refuses_missing_empty_and_nested_values() {
+        let template = Template::new(SPLIT_LAYOUT, &FOLDER_FIELDS, "--layout").unwrap();
+        assert!(template.render(&values(&[("type", "pep")])).is_err());
+        assert!(template
+            .render(&values(&[("accession", ""), ("type", "pep")]))
+            .is_err());
+        assert!(template
+            .render(&values(&[("accession", "../GCA_1"), ("type", "pep")]))
+            .is_err());
+    }
+
+    #[test]
+    fn base_name_of_ensembl_files() {
+        assert_eq!(
+            base_name("Homo_sapiens.GRCh38.pep.all.fa.gz"),
+            "Homo_sapiens.GRCh38"
+        );
+        assert_eq!(
+            base_name("/data/Homo_sapiens.GRCh38.cdna.all.fa"),
+            "Homo_sapiens.GRCh38"
+        );
+        // The release is kept, it tells inputs apart
+        assert_eq!(
+            base_name("Danio_rerio.GRCz11.113.ncrna.fa.gz"),
+            "Danio_rerio.GRCz11.113"
+        );
+    }
+
+    #[test]
+    fn base_name_of_ncbi_files() {
+        assert_eq!(
+            base_name("GCF_000001405.40_GRCh38.p14_protein.faa"),
+            "GCF_000001405.40_GRCh38.p14"
+        );
+        assert_eq!(
+            base_name("GCF_000001405.40_GRCh38.p14_cds_from_genomic.fna.gz"),
+            "GCF_000001405.40_GRCh38.p14"
+        );
+        assert_eq!(
+            base_name("GCF_000001405.40_GRCh38.p14_rna.fna"),
+            "GCF_000001405.40_GRCh38.p14"
+        );
+    }
+
+    #[test]
+    fn base_name_keeps_the_first_token() {
+        assert_eq!(base_name("pep.fa"), "pep");
+        assert_eq!(base_name("cdna.FA.GZ"), "cdna");
+        assert_eq!(base_name("proteins"), "proteins");
+    }
+}
@@ -0,0 +1,409 @@
+use super::dialect::Dialect;
+use super::layout::Layout;
+use super::{file_md5, fix_head};
+use crate::cli::ManifestType;
+use noodles::fasta;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str;
+
+/// One row of the manifest, describes a single chunk written by a split command
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkSummary {
+    pub path: String,
+    pub records: usize,
+    pub residues: usize,
+    pub bytes: u64,
+    pub first_id: String,
+    pub last_id: String,
+    pub md5: String,
+}
+
+impl ChunkSummary {
+    pub fn new(path: &Path) -> Self {
+        ChunkSummary {
+            path: path.to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add_record(&mut self, record: &fasta::Record) {
+        let id = str::from_utf8(record.name())
+            .unwrap_or_default()
+            .to_string();
+        if self.records == 0 {
+            self.first_id = id.clone();
+        }
+        self.last_id = id;
+        self.records += 1;
+        self.residues += record.sequence().len();
+    }
+}
+
+/// Everything a downstream step needs to know about a split run
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub tool_version: String,
+    pub command_line: Vec<String>,
+    // The options which decide what ends up in each chunk
+    pub settings: String,
+    pub chunks: Vec<ChunkSummary>,
+}
+
+impl Manifest {
+    pub fn new(
+        command_line: Option<Vec<String>>,
+        settings: String,
+        chunks: Vec<ChunkSummary>,
+    ) -> Self {
+        Manifest {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            command_line: command_line.unwrap_or_else(|| std::env::args().collect()),
+            settings,
+            chunks,
+        }
+    }
+
+    pub fn read(path: &Path, format: &ManifestType) -> io::Result<Self> {
+        match format {
+            ManifestType::Json => Ok(serde_json::from_reader(File::open(path)?)?),
+            ManifestType::Tsv => {
+                let text = fs::read_to_string(path)?;
+                let mut manifest = Manifest::new(None, String::new(), Vec::new());
+                let mut table = String::new();
+                for line in text.lines() {
+                    if let Some(version) = line.strip_prefix("#tool_version\t") {
+                        manifest.tool_version = version.to_string();
+                    } else if let Some(command) = line.strip_prefix("#command_line\t") {
+                        manifest.command_line = shlex::split(command).ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!(
+                                    "{}: can't split #command_line {}",
+                                    path.display(),
+                                    command
+                                ),
+                            )
+                        })?;
+                    } else if let Some(settings) = line.strip_prefix("#settings\t") {
+                        manifest.settings = settings.to_string();
+                    } else {
+                        table.push_str(line);
+                        table.push('\n');
+                    }
+                }
+                let mut rdr = csv::ReaderBuilder::new()
+                    .delimiter(b'\t')
+                    .from_reader(table.as_bytes());
+                for result in rdr.deserialize() {
+                    manifest.chunks.push(result?);
+                }
+                Ok(manifest)
+            }
+        }
+    }
+
+    pub fn add_chunk(
+        &mut self,
+        chunk: ChunkSummary,
+        path: &Path,
+        format: &ManifestType,
+    ) -> io::Result<()> {
+        // Rewritten after every chunk so a run that dies part way
+        // still leaves a record of what finished, for --resume
+        self.chunks.push(chunk);
+        self.write(path, format)
+    }
+
+    pub fn finish(&self, path: &Path, format: &ManifestType) -> io::Result<()> {
+        self.write(path, format)?;
+        println!("Manifest written to: {}", path.display());
+        Ok(())
+    }
+
+    fn write(&self, path: &Path, format: &ManifestType) -> io::Result<()> {
+        // The manifest always describes the latest run, so it is simply
+        // replaced, via a temp file like the chunks themselves
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("tmp");
+        let mut file = File::create(&temp_path)?;
+        match format {
+            ManifestType::Json => {
+                serde_json::to_writer_pretty(&mut file, self)?;
+                writeln!(file)?;
+            }
+            ManifestType::Tsv => {
+                // Run details go in # comment lines above the chunk table
+                writeln!(file, "#tool_version\t{}", self.tool_version)?;
+                // Quoted as a shell would, so arguments with spaces read back whole
+                let command_line = shlex::try_join(self.command_line.iter().map(String::as_str))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                writeln!(file, "#command_line\t{}", command_line)?;
+                writeln!(file, "#settings\t{}", self.settings)?;
+                let mut wtr = csv::WriterBuilder::new().delimiter(b'\t').from_writer(file);
+                for chunk in &self.chunks {
+                    wtr.serialize(chunk)?;
+                }
+                wtr.flush()?;
+            }
+        }
+        fs::rename(&temp_path, path)
+    }
+}
+
+pub fn manifest_path(layout: &Layout, format: &ManifestType) -> String {
+    // Kept one level above the data folder so gencsv doesn't see it as a data file
+    let suffix = match format {
+        ManifestType::Tsv => "tsv",
+        ManifestType::Json => "json",
+    };
+    layout.sidecar(&format!("manifest.{}", suffix))
+}
+
+/// The chunks a --resume run can keep from the last attempt
+#[derive(Debug, Default)]
+pub struct Resume {
+    pub chunks: Vec<ChunkSummary>,
+    boundaries: HashMap<usize, String>,
+}
+
+impl Resume {
+    pub fn load(path: &Path, format: &ManifestType, settings: &str) -> io::Result<Self> {
+        // Keep chunks from the start of the manifest for as long as the
+        // file on disk still matches its checksum, the first one that is
+        // missing or changed is where the run carries on from
+        let mut resume = Resume::default();
+        if !path.exists() {
+            println!("No manifest at {}, starting from scratch", path.display());
+            return Ok(resume);
+        }
+
+        let manifest = Manifest::read(path, format)?;
+        if manifest.settings != settings {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The last run used '{}' but this one uses '{}', can't resume with different settings",
+                    manifest.settings, settings
+                ),
+            ));
+        }
+
+        let mut start = 0;
+        for chunk in manifest.chunks {
+            let chunk_path = Path::new(&chunk.path);
+            if !chunk_path.exists() || file_md5(chunk_path)? != (chunk.bytes, chunk.md5.clone()) {
+                println!("Incomplete or changed file: {}", chunk.path);
+                break;
+            }
+            // No split writes an empty chunk, so the manifest has been edited
+            if chunk.records == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} lists {} with no records, can't resume from it",
+                        path.display(),
+                        chunk.path
+                    ),
+                ));
+            }
+            resume.boundaries.insert(start, chunk.first_id.clone());
+            resume
+                .boundaries
+                .insert(start + chunk.records - 1, chunk.last_id.clone());
+            start += chunk.records;
+            resume.chunks.push(chunk);
+        }
+        println!(
+            "Resuming after {} complete files ({} records)",
+            resume.chunks.len(),
+            resume.records()
+        );
+        Ok(resume)
+    }
+
+    pub fn records(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.records).sum()
+    }
+
+    pub fn is_boundary(&self, index: usize) -> bool {
+        self.boundaries.contains_key(&index)
+    }
+
+    pub fn check(
+        &self,
+        index: usize,
+        record: &fasta::Record,
+        dialect: Option<&Dialect>,
+    ) -> io::Result<()> {
+        // The records being skipped should be the ones the kept chunks
+        // start and end with, otherwise the input or settings have changed.
+        // A sanitised header may have been given a _N suffix or swapped
+        // for the original ID by --on-collision
+        let Some(expected) = self.boundaries.get(&index) else {
+            return Ok(());
+        };
+        let original = String::from_utf8_lossy(record.name()).to_string();
+        let name = String::from_utf8_lossy(fix_head(record.clone(), dialect).name()).to_string();
+        let suffixed = expected
+            .strip_prefix(&format!("{}_", name))
+            .is_some_and(|suffix| suffix.chars().all(|c| c.is_ascii_digit()));
+        if *expected == name || *expected == original || suffixed {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Record {} is {} but the manifest expects {}, the input or settings have changed so this run can't be resumed",
+                index + 1,
+                name,
+                expected
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::scratch::Scratch;
+    use noodles::fasta::record::{Definition, Sequence};
+    use std::path::PathBuf;
+
+    const SETTINGS: &str = "splitcount chunk_size=2 n_files=None";
+
+    fn record(id: &str) -> fasta::Record {
+        fasta::Record::new(Definition::new(id, None), Sequence::from(b"MKV".to_vec()))
+    }
+
+    fn chunk(dir: &Path, file_name: &str, ids: &[&str]) -> ChunkSummary {
+        let path = dir.join(file_name);
+        let text: String = ids.iter().map(|id| format!(">{}\nMKV\n", id)).collect();
+        fs::write(&path, text).unwrap();
+        let mut chunk = ChunkSummary::new(&path);
+        for id in ids {
+            chunk.add_record(&record(id));
+        }
+        (chunk.bytes, chunk.md5) = file_md5(&path).unwrap();
+        chunk
+    }
+
+    fn partial_run(name: &str, format: &ManifestType) -> (Scratch, PathBuf) {
+        // Three chunks written, the last cut short by the run dying
+        let dir = Scratch::new(name);
+        let manifest_file = dir.join("manifest");
+        let mut manifest = Manifest::new(None, SETTINGS.to_string(), Vec::new());
+        for (file_name, ids) in [
+            ("f1.fa", ["a", "b"]),
+            ("f2.fa", ["c_2", "d"]),
+            ("f3.fa", ["e", "f"]),
+        ] {
+            let chunk = chunk(&dir, file_name, &ids);
+            manifest.add_chunk(chunk, &manifest_file, format).unwrap();
+        }
+        fs::write(dir.join("f3.fa"), ">e\nMK").unwrap();
+        (dir, manifest_file)
+    }
+
+    #[test]
+    fn resume_keeps_the_complete_chunks() {
+        for format in [ManifestType::Tsv, ManifestType::Json] {
+            let (_dir, manifest_file) = partial_run(&format!("resume-{:?}", format), &format);
+            let resume = Resume::load(&manifest_file, &format, SETTINGS).unwrap();
+            assert_eq!(resume.chunks.len(), 2);
+            assert_eq!(resume.records(), 4);
+            assert_eq!((0..6).filter(|index| resume.is_boundary(*index)).count(), 4);
+
+            // The same input, a _N suffix from a collision is allowed for
+            assert!(resume.check(0, &record("a"), None).is_ok());
+            assert!(resume.check(1, &record("b"), None).is_ok());
+            assert!(resume.check(2, &record("c"), None).is_ok());
+            assert!(resume.check(3, &record("d"), None).is_ok());
+            // Only chunk boundaries are checked
+            assert!(resume.check(4, &record("anything"), None).is_ok());
+            // A different input
+            let error = resume.check(1, &record("x"), None).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(resume.check(2, &record("c_x"), None).is_err());
+        }
+    }
+
+    #[test]
+    fn resume_stops_at_a_missing_chunk() {
+        let (dir, manifest_file) = partial_run("resume-missing", &ManifestType::Tsv);
+        fs::remove_file(dir.join("f2.fa")).unwrap();
+        let resume = Resume::load(&manifest_file, &ManifestType::Tsv, SETTINGS).unwrap();
+        assert_eq!(resume.chunks.len(), 1);
+        assert_eq!(resume.records(), 2);
+    }
+
+    #[test]
+    fn command_lines_read_back_whole() {
+        for format in [ManifestType::Tsv, ManifestType::Json] {
+            let dir = Scratch::new(&format!("manifest-command-{:?}", format));
+            let manifest_file = dir.join("manifest");
+            let command_line: Vec<String> = [
+                "treeval_utils",
+                "splitcount",
+                "-f",
+                "my genes/a.fa",
+                "--dedupe-prefer",
+                "^>NP_ .*'curated'",
+                "",
+            ]
+            .map(String::from)
+            .to_vec();
+            let manifest =
+                Manifest::new(Some(command_line.clone()), SETTINGS.to_string(), Vec::new());
+            manifest.finish(&manifest_file, &format).unwrap();
+            let read = Manifest::read(&manifest_file, &format).unwrap();
+            assert_eq!(read.command_line, command_line);
+            assert_eq!(read.settings, SETTINGS);
+        }
+    }
+
+    #[test]
+    fn resume_refuses_an_empty_chunk() {
+        for format in [ManifestType::Tsv, ManifestType::Json] {
+            let dir = Scratch::new(&format!("resume-empty-{:?}", format));
+            let manifest_file = dir.join("manifest");
+            let mut manifest = Manifest::new(None, SETTINGS.to_string(), Vec::new());
+            for (file_name, ids) in [("f1.fa", &["a", "b"][..]), ("f2.fa", &[])] {
+                let chunk = chunk(&dir, file_name, ids);
+                manifest.add_chunk(chunk, &manifest_file, &format).unwrap();
+            }
+            let error = Resume::load(&manifest_file, &format, SETTINGS).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(error.to_string().contains("f2.fa with no records"));
+        }
+    }
+
+    #[test]
+    fn resume_refuses_different_settings() {
+        let (_dir, manifest_file) = partial_run("resume-settings", &ManifestType::Tsv);
+        let error = Resume::load(
+            &manifest_file,
+            &ManifestType::Tsv,
+            "splitcount chunk_size=3 n_files=None",
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error
+            .to_string()
+            .contains("can't resume with different settings"));
+    }
+
+    #[test]
+    fn resume_without_a_manifest_starts_from_scratch() {
+        let dir = Scratch::new("resume-none");
+        let missing = dir.join("manifest.tsv");
+        let resume = Resume::load(&missing, &ManifestType::Tsv, SETTINGS).unwrap();
+        assert!(resume.chunks.is_empty());
+        assert_eq!(resume.records(), 0);
+    }
+}
@@ -1,288 +1,421 @@
-use noodles::fasta;
-use noodles::fasta::record::Definition;
-use once_cell::sync::Lazy;
-use regex::{self, Regex};
+pub mod annotation;
+pub mod collision;
+pub mod dedupe;
+pub mod detect;
+pub mod dialect;
+pub mod header;
+pub mod isoform;
+pub mod layout;
+pub mod manifest;
+pub mod pipeline;
+pub mod plan;
+pub mod qc;
+pub mod rename;
+#[cfg(test)]
+pub mod scratch;
+pub mod translate;
+
+use crate::cli::{DType, ExistingFiles, SplitOptions};
+use colored::Colorize;
+use dialect::Dialect;
+use flate2::read::MultiGzDecoder;
+use manifest::ChunkSummary;
+use md5::{Digest, Md5};
+use noodles::{bgzf, fasta};
 use std::error::Error;
 use std::fs::{self, File, OpenOptions};
 use std::{
-    collections::HashMap,
-    io::{self, BufRead},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write},
     path::{Path, PathBuf},
-    result, str,
+    str,
 };
 
-#[allow(clippy::iter_kv_map)]
-pub fn only_keys<K, V>(map: HashMap<K, V>) -> impl Iterator<Item = K> {
-    // Take a HashMap and return a Key only Vec
-    map.into_iter().map(|(k, _v)| k)
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Compression {
+    Plain,
+    Gzip,
+    Bgzf,
 }
 
-pub fn validate_fasta(
-    path: &str,
-) -> result::Result<HashMap<std::string::String, usize>, Box<dyn Error>> {
-    // Simply validate the fasta is valid by reading though and ensure there are
-    // valid record formats through out the file
-    // Return a Dict of header and length
-    let reader: Result<fasta::Reader<Box<dyn BufRead>>, std::io::Error> =
-        fasta::reader::Builder.build_from_path(path);
-    let mut fasta_map = HashMap::new();
-
-    match &reader {
-        Ok(_fasta) => {
-            let mut binding: fasta::Reader<Box<dyn BufRead>> =
-                reader.expect("NO VALID HEADER / SEQUENCE PAIRS");
-            for result in binding.records() {
-                let record = result?;
-                fasta_map.insert(
-                    str::from_utf8(record.name())?.to_string(),
-                    record.sequence().len(),
-                );
-            }
-            Ok(fasta_map)
+pub fn detect_compression(path: &str) -> io::Result<Compression> {
+    // Sniff the magic bytes rather than trusting the suffix
+    // gzip starts 1f 8b 08, BGZF is gzip with FEXTRA set and a
+    // 'BC' subfield as the first extra field (SAM spec 4.1)
+    let mut magic = [0u8; 14];
+    let mut file = File::open(path)?;
+    let mut read = 0;
+    while read < magic.len() {
+        match file.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
         }
-        Err(_) => Err("Error: Fasta is not valid check file!".into()),
     }
-}
 
-pub fn ensure_file_exists(file_path: &str) -> std::io::Result<()> {
-    // Check if the file exists
-    if !Path::new(file_path).exists() {
-        // We don't need to return the FILE object, it just needs to be made
-        let _ = File::create(file_path)?;
+    if read < 3 || magic[0..3] != [0x1f, 0x8b, 0x08] {
+        Ok(Compression::Plain)
+    } else if read == magic.len() && magic[3] & 0x04 != 0 && magic[12..14] == [b'B', b'C'] {
+        Ok(Compression::Bgzf)
+    } else {
+        Ok(Compression::Gzip)
     }
-    Ok(())
 }
 
-pub fn write_fasta(
-    outdir: &String,
-    file_name: String,
-    fasta_record: Vec<noodles::fasta::Record>,
-) -> std::io::Result<()> {
-    // Create file
-    fs::create_dir_all(outdir)?;
-    let file_path = format!("{}/{}", outdir, file_name);
-    ensure_file_exists(&file_path).unwrap();
-
-    // Append to file
-    let file = OpenOptions::new()
-        .append(true)
-        .open(file_path)
-        .expect("creation failed");
-
-    let mut writer = fasta::Writer::new(file);
-    for i in fasta_record {
-        writer.write_record(&i).unwrap();
-    }
-    Ok(())
+pub fn open_text(path: &str) -> io::Result<Box<dyn BufRead>> {
+    // Streaming reader for plain, gzip and bgzip files
+    let file = File::open(path)?;
+    Ok(match detect_compression(path)? {
+        Compression::Plain => Box::new(BufReader::new(file)),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        Compression::Bgzf => Box::new(bgzf::Reader::new(file)),
+    })
 }
 
-// Function to list directories
-pub fn get_folder_list(dir_loc: &str) -> Vec<PathBuf> {
-    fs::read_dir(dir_loc)
-        .unwrap()
-        .filter_map(|entry| {
-            let path = entry.unwrap().path();
-            if path.is_dir() {
-                Some(path)
-            } else {
-                None
+pub fn open_fasta(path: &str) -> io::Result<fasta::Reader<Box<dyn BufRead>>> {
+    Ok(fasta::Reader::new(open_text(path)?))
+}
+
+pub trait BufReadSeek: BufRead + Seek {}
+impl<T: BufRead + Seek> BufReadSeek for T {}
+
+pub fn open_indexed_fasta(
+    path: &str,
+) -> io::Result<Option<fasta::IndexedReader<Box<dyn BufReadSeek>>>> {
+    // Random access needs a {path}.fai and, for bgzip, a {path}.gzi.
+    // None if there isn't one, or it is older than the input, so the
+    // caller can stream the input instead.
+    // Plain gzip can't be seeked so is always streamed
+    let fai = format!("{}.fai", path);
+    let modified = |path: &str| fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(&fai), modified(path)) {
+        (Ok(indexed), Ok(written)) if indexed >= written => {}
+        (Ok(_), Ok(_)) => {
+            println!(
+                "{} {} is older than the input, not using it",
+                "WARNING:".yellow(),
+                fai
+            );
+            return Ok(None);
+        }
+        _ => return Ok(None),
+    }
+
+    let file = File::open(path)?;
+    let inner: Box<dyn BufReadSeek> = match detect_compression(path)? {
+        Compression::Plain => Box::new(BufReader::new(file)),
+        Compression::Bgzf => match bgzf::gzi::read(format!("{}.gzi", path)) {
+            Ok(gzi) => Box::new(bgzf::IndexedReader::new(file, gzi)),
+            Err(_) => {
+                println!(
+                    "{} {} is bgzip compressed but has no readable .gzi, not using {}",
+                    "WARNING:".yellow(),
+                    path,
+                    fai
+                );
+                return Ok(None);
             }
+        },
+        Compression::Gzip => {
+            println!(
+                "{} {} is gzip compressed and can't be indexed, recompress with bgzip to use {}",
+                "WARNING:".yellow(),
+                path,
+                fai
+            );
+            return Ok(None);
+        }
+    };
+    let index = fasta::fai::read(&fai)?;
+    let reader = fasta::indexed_reader::Builder::default()
+        .set_index(index)
+        .build_from_reader(inner)?;
+    Ok(Some(reader))
+}
+
+pub fn index_sizes(reader: &fasta::IndexedReader<Box<dyn BufReadSeek>>) -> Vec<(String, usize)> {
+    // The same header and length list as validate_fasta, from the .fai
+    reader
+        .index()
+        .as_ref()
+        .iter()
+        .map(|record| {
+            (
+                String::from_utf8_lossy(record.name()).to_string(),
+                record.length() as usize,
+            )
         })
         .collect()
 }
 
-pub fn nothing() -> io::Result<()> {
-    // This was required to get around an if block returning
-    // mismatching types in cli.
-    Ok(())
+pub fn validate_fasta(path: &str) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
+    // Simply validate the fasta is valid by reading though and ensure there are
+    // valid record formats through out the file
+    // Return a Vec of header and length, in input order
+    let mut reader = match open_fasta(path) {
+        Ok(reader) => reader,
+        Err(_) => return Err("Error: Fasta is not valid check file!".into()),
+    };
+
+    let mut fasta_list = Vec::new();
+    for result in reader.records() {
+        let record = result?;
+        fasta_list.push((
+            str::from_utf8(record.name())?.to_string(),
+            record.sequence().len(),
+        ));
+    }
+    Ok(fasta_list)
 }
 
-fn get_ensemble_symbol(header: String) -> Result<String, Box<dyn std::error::Error>> {
-    //Ideal case is:
-    // >protein_id=ENSMUSP00000070648.5;gene_id=ENSMUSG00000051951.6;transcript_id=ENSMUST00000070533.5;gene_name=Xkr4-201
-    //from
-    // >ENSMUSP00000137363.3|ENSMUST00000178446.3|ENSMUSG00000096178.8|OTTMUSG00000047352.1|-|Gm20837-201|Gm20837|222
-
-    // Can I replace all of the below with something like:
-    // static MASTER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?<prot_id>ENS\w+P\d+.[0-9*])|(?<gene_id>ENS\w+G\d+.[0-9*])|(?<trans_id>ENS\w+T\d+.[0-9*])").unwrap());
-    //
-
-    // Alternate Case...
-    // >AT5G16970.1 pep chromosome:TAIR10:5:5575973:5578086:-1 gene:AT5G16970 transcript:AT5G16970.1 gene_biotype:protein_coding transcript_biotype:protein_coding gene_symbol:AER description:alkenal reductase [Source:NCBI gene (formerly Entrezgene);Acc:831560]
-    // Quite different
-
-    // Turning the REGEX compilations into Lazy statics turned testing on a 35Mb genome
-    // from 16 to 5 minutes!!!
-    // This is because this function is used inside a loop
-    // so the regex was being compiled every loop
-    // Lazy ensures they are compiled only_once (name of the crate... get it)
-    // This is still a stupid amount of time but an 11 minute speed up is amazing
-    static RE_1A: Lazy<Regex> = Lazy::new(|| Regex::new(r"ENS\w+G\d+.[0-9*]").unwrap());
-    static RE_1B: Lazy<Regex> = Lazy::new(|| Regex::new(r"gene:(?<gene_id>\S.*?)\s").unwrap());
-    let ens_gene_capture = RE_1A
-        .captures(&header)
-        .and_then(|caps| caps.get(0))
-        .map_or("NO_GENE_NAME", |m| m.as_str());
-    let final_gene_id = if ens_gene_capture == "NO_GENE_NAME" {
-        let ens_gene_backup: String = match RE_1B.captures(&header) {
-            Some(i) => format!("gene_id={};", i.name("gene_id").unwrap().as_str()),
-            None => "".to_string(),
-        };
-        if ens_gene_backup == "NO_GENE_NAME" {
-            "".to_string()
+#[derive(Debug, PartialEq, Eq)]
+enum ChunkMode {
+    Write,
+    // --skip-existing found the chunk already there
+    Keep,
+    // --dry-run, only the summary is made
+    Plan,
+}
+
+/// A chunk on its way to disk. It is written to a hidden temp file and
+/// renamed into place by finish, so a chunk is either complete or not
+/// there at all, even if the run is killed. Bytes and MD5 are counted on
+/// the way so the manifest doesn't need to read every chunk back.
+/// The file can be closed part way and is appended to when the next
+/// record comes, for splits that fill many chunks at once
+pub struct ChunkFile {
+    file_path: PathBuf,
+    temp_path: PathBuf,
+    summary: ChunkSummary,
+    hasher: Md5,
+    file: Option<BufWriter<File>>,
+    started: bool,
+    mode: ChunkMode,
+}
+
+impl ChunkFile {
+    pub fn create(
+        outdir: &str,
+        file_name: &str,
+        existing: &ExistingFiles,
+        dry_run: bool,
+    ) -> io::Result<Self> {
+        // Nothing is opened until the first record, checked up front so
+        // a clash stops the run before any reading
+        let file_path = Path::new(outdir).join(file_name);
+        let mut mode = if dry_run {
+            ChunkMode::Plan
         } else {
-            ens_gene_backup
+            ChunkMode::Write
+        };
+        if !dry_run {
+            fs::create_dir_all(outdir)?;
         }
-    } else {
-        format!("gene_id={};", ens_gene_capture)
-    };
+        if !dry_run && file_path.exists() {
+            match existing {
+                ExistingFiles::Fail => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!(
+                        "{} already exists, use --force to overwrite or --skip-existing to keep it",
+                        file_path.display()
+                    ),
+                    ))
+                }
+                ExistingFiles::Skip => {
+                    println!("Keeping existing file: {}", file_path.display());
+                    mode = ChunkMode::Keep;
+                }
+                ExistingFiles::Overwrite => {}
+            }
+        }
+        Ok(ChunkFile {
+            temp_path: Path::new(outdir).join(format!(".{}.tmp", file_name)),
+            summary: ChunkSummary::new(&file_path),
+            file_path,
+            hasher: Md5::new(),
+            file: None,
+            started: false,
+            mode,
+        })
+    }
 
-    static RE_2: Lazy<Regex> = Lazy::new(|| Regex::new(r"ENS\w+P\d+.[0-9*]").unwrap());
-    let ens_prot_capture = RE_2
-        .captures(&header)
-        .and_then(|caps| caps.get(0))
-        .map_or("NO_PROT_NAME", |m| m.as_str());
-    let final_prot_name = if ens_prot_capture == "NO_PROT_NAME" {
-        "".to_string()
-    } else {
-        format!("protein_id={};", ens_prot_capture)
-    };
+    pub fn path(&self) -> &str {
+        &self.summary.path
+    }
 
-    static RE_3: Lazy<Regex> = Lazy::new(|| Regex::new(r"ENS\w+T\d+.[0-9*]").unwrap());
-    static RE_3B: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r"transcript:(?<trans_id>\S.*?)\s").unwrap());
-
-    let ens_trans_capture = RE_3
-        .captures(&header)
-        .and_then(|caps| caps.get(0))
-        .map_or("NO_TRAN_NAME", |m| m.as_str());
-    let final_trans_name = if ens_trans_capture == "NO_TRAN_NAME" {
-        let ens_trans_backup: String = match RE_3B.captures(&header) {
-            Some(i) => format!("transcript_id={};", i.name("trans_id").unwrap().as_str()),
-            None => "".to_string(),
-        };
-        if ens_trans_backup == "NO_TRANS_NAME" {
-            "".to_string()
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        // A new temp file the first time, appended to after that
+        let file = if self.started {
+            OpenOptions::new().append(true).open(&self.temp_path)?
         } else {
-            ens_trans_backup
+            File::create(&self.temp_path)?
+        };
+        self.started = true;
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    pub fn write_record(&mut self, record: &fasta::Record) -> io::Result<()> {
+        if self.mode == ChunkMode::Keep {
+            return Ok(());
         }
-    } else {
-        format!("transcript_id={};", ens_trans_capture)
-    };
+        // Records are formatted one at a time so the byte count is exact
+        let mut formatted = fasta::Writer::new(Vec::new());
+        formatted.write_record(record)?;
+        let formatted = formatted.into_inner();
+        self.summary.add_record(record);
+        self.summary.bytes += formatted.len() as u64;
+        if self.mode == ChunkMode::Plan {
+            return Ok(());
+        }
+        self.hasher.update(&formatted);
+        if self.file.is_none() {
+            self.open()?;
+        }
+        self.file.as_mut().unwrap().write_all(&formatted)
+    }
 
-    let gene_name: Vec<&str> = header.split('|').collect();
+    pub fn close(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            Some(mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
 
-    let final_gene_name = if gene_name.len() > 3 {
-        let final_gname = gene_name[gene_name.len() - 2];
+    pub fn finish(mut self) -> io::Result<ChunkSummary> {
+        match self.mode {
+            ChunkMode::Keep => return summarise_fasta(&self.file_path),
+            ChunkMode::Plan => return Ok(self.summary),
+            ChunkMode::Write => {}
+        }
+        if self.file.is_none() {
+            self.open()?;
+        }
+        let file = self.file.take().unwrap();
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        self.summary.md5 = format!("{:x}", self.hasher.finalize());
+        fs::rename(&self.temp_path, &self.file_path)?;
+        Ok(self.summary)
+    }
 
-        format!("gene_name={}", final_gname)
-    } else {
-        "".to_string()
-    };
+    pub fn discard(mut self) {
+        // A failed run leaves no temp files behind
+        self.file = None;
+        if self.started {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
 
-    Ok(format!(
-        "{}{}{}{}",
-        final_prot_name, final_gene_id, final_trans_name, final_gene_name,
-    ))
+pub fn write_fasta(
+    outdir: &str,
+    file_name: String,
+    fasta_record: Vec<noodles::fasta::Record>,
+    existing: &ExistingFiles,
+) -> std::io::Result<ChunkSummary> {
+    let mut chunk = ChunkFile::create(outdir, &file_name, existing, false)?;
+    for i in fasta_record {
+        chunk.write_record(&i)?;
+    }
+    chunk.finish()
 }
 
-fn get_ncbi_symbol(header: String) -> Result<String, Box<dyn std::error::Error>> {
-    println!("NCBI -- SANITISE HEADERS -- EXPERIMENTAL");
-    // Take a string and return first segment of it
-    //let header_list: Vec<&str> = header.split(' ').collect();
-    //let record_header = header_list[0];
-    //Ok(record_header[1..].to_owned())
-
-    // Ugly nested Lazy Regex
-    static RE_1A: Lazy<Regex> = Lazy::new(|| Regex::new(r"(NP_\S+)|(XP_\S+)").unwrap());
-    static RE_1B: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[protein_id=(.*?)\]").unwrap());
-
-    let ncbi_protein_name_capture = RE_1A
-        .captures(&header)
-        .and_then(|caps| caps.get(0))
-        .map_or("NO_PROT_NAME", |m| m.as_str());
-    let final_prot_name = if ncbi_protein_name_capture == "NO_PROT_NAME" {
-        let ncbi_prot_backup = RE_1B
-            .captures(&header)
-            .and_then(|caps| caps.get(0))
-            .map_or("NO_PROT_NAME", |m| m.as_str());
-        if ncbi_prot_backup == "NO_PROT_NAME" {
-            "".to_string()
-        } else {
-            format!("protein_id={};", ncbi_prot_backup)
-        }
-    } else {
-        format!("protein_id={};", ncbi_protein_name_capture)
-    };
+pub fn plan_fasta(
+    outdir: &str,
+    file_name: String,
+    fasta_record: Vec<noodles::fasta::Record>,
+) -> io::Result<ChunkSummary> {
+    // The summary write_fasta would give, without touching the disk
+    let mut chunk = ChunkFile::create(outdir, &file_name, &ExistingFiles::Fail, true)?;
+    for i in fasta_record {
+        chunk.write_record(&i)?;
+    }
+    chunk.finish()
+}
 
-    static RE_2: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[gene=(.*?)\]").unwrap());
-    let ncbi_gene_capture = RE_2
-        .captures(&header)
-        .and_then(|caps| caps.get(0))
-        .map_or("NO_GENE_NAME", |m| m.as_str());
-    let final_gene_id = if ncbi_gene_capture == "NO_GENE_NAME" {
-        "".to_string()
-    } else {
-        format!("gene_id={};", ncbi_gene_capture)
-    };
+pub fn summarise_fasta(file_path: &Path) -> io::Result<ChunkSummary> {
+    // Build the manifest row for a FASTA already on disk
+    let mut summary = ChunkSummary::new(file_path);
+    let path = file_path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a UTF-8 path", file_path.display()),
+        )
+    })?;
+    let mut reader = open_fasta(path)?;
+    for result in reader.records() {
+        summary.add_record(&result?);
+    }
 
-    static RE_3: Lazy<Regex> = Lazy::new(|| Regex::new(r"(NM_\S+)\s").unwrap());
-    let ncbi_tran_capture = RE_3
-        .captures(&header)
-        .and_then(|caps| caps.get(0))
-        .map_or("NO_TRAN_NAME", |m| m.as_str());
-    let final_tran_name = if ncbi_tran_capture == "NO_TRAN_NAME" {
-        "".to_string()
-    } else {
-        format!("transcript_id={};", ncbi_tran_capture)
-    };
+    (summary.bytes, summary.md5) = file_md5(file_path)?;
+    Ok(summary)
+}
 
-    Ok(format!(
-        "{}{}{}",
-        final_prot_name, final_gene_id, final_tran_name
-    ))
-    // let re = Regex::new(r"gene=([A-Z]\w+)").unwrap();
-
-    // let first_run = re.captures(&header).ok_or("None")?;
-
-    // if first_run[0] == "None".to_owned() {
-    //     let re = Regex::new(r"symbol:(\S+)").unwrap();
-    //     let second_run = re.captures(&header).ok_or("None")?;
-    //     if second_run[0] == "None".to_owned() {
-    //         let re = Regex::new(r"(\(\S+\)) gene").unwrap();
-    //         let third_run = re.captures(&header).ok_or("None")?;
-    //         if third_run[0] == "None".to_owned() {
-    //             Ok("NOCAPTUREDRESULT".to_string())
-    //         } else {
-    //             Ok(third_run[0].to_string())
-    //         }
-    //     } else {
-    //         Ok(second_run[0].to_string())
-    //     }
-    // } else {
-    //     Ok(first_run[0].to_string())
-    // }
+pub fn file_md5(file_path: &Path) -> io::Result<(u64, String)> {
+    // Size and MD5 of a file, in the form the manifest stores them
+    let mut hasher = Md5::new();
+    let bytes = io::copy(&mut File::open(file_path)?, &mut hasher)?;
+    Ok((bytes, format!("{:x}", hasher.finalize())))
 }
 
-pub fn sanitise_header(old_header: &Definition, origin_db: &str) -> String {
-    // Clean the header
-    // This is overly complex for historical reasons
-    // It is still here incase those reasons come back to haunt me
-    // ...again
-    //
-    let new_header = match origin_db {
-        "ensembl" => get_ensemble_symbol(old_header.to_string()),
-        "ncbi" => get_ncbi_symbol(old_header.to_string()),
-        "other" => {
-            panic!("What DB fasta do you want parsed?")
+// The folders the built-in data types are written to
+pub const DATA_TYPE_FOLDERS: [&str; 4] = ["pep", "cdna", "rna", "cds"];
+
+pub fn data_type_folder(options: &SplitOptions) -> io::Result<String> {
+    // The folder the chunks go in, named after -d, or after --label
+    // for -d other
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    match (options.data_type(), &options.label) {
+        (DType::Pep, None) => Ok("pep".to_string()),
+        (DType::Cdna, None) => Ok("cdna".to_string()),
+        (DType::Rna, None) => Ok("rna".to_string()),
+        (DType::Cds, None) => Ok("cds".to_string()),
+        (DType::Other, Some(label)) => {
+            if label.is_empty()
+                || !label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                Err(invalid(format!(
+                    "--label {} can only use letters, digits, _ and -",
+                    label
+                )))
+            } else if DATA_TYPE_FOLDERS.contains(&label.to_lowercase().as_str()) {
+                Err(invalid(format!(
+                    "--label {} is already used by a built-in data type, use -d {} instead",
+                    label, label
+                )))
+            } else {
+                Ok(label.clone())
+            }
         }
-        _ => panic!("What origin db was this from?"),
-    };
+        (DType::Other, None) => Err(invalid(
+            "-d other needs a --label to name its output folder, e.g. --label ncrna".to_string(),
+        )),
+        (_, Some(_)) => Err(invalid("--label is only used with -d other".to_string())),
+    }
+}
+
+pub fn nothing() -> io::Result<()> {
+    // This was required to get around an if block returning
+    // mismatching types in cli.
+    Ok(())
+}
+
+pub fn fix_head(records: fasta::Record, dialect: Option<&Dialect>) -> fasta::Record {
+    // Taker a Record and sanitise the header with the dialect
+    // recombine into a new Record
+    match dialect {
+        Some(dialect) => {
+            let header = dialect.sanitise(&records.definition().to_string());
+            let definition = fasta::record::Definition::new(header, None);
+            let seq = records.sequence().to_owned();
 
-    match new_header {
-        Ok(c) => c,
-        Err(e) => {
-            format!("Error: {}", e)
+            fasta::Record::new(definition, seq)
         }
+        None => records,
     }
 }
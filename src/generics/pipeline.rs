@@ -0,0 +1,386 @@
+use super::collision::{HeaderTracker, InOrder};
+use super::dialect::Dialect;
+use super::manifest::{ChunkSummary, Manifest};
+use super::plan::Plan;
+use super::rename::{kept_rows, RenameMap, RenameRow};
+use super::{plan_fasta, write_fasta, ChunkFile};
+use crate::cli::SplitOptions;
+use noodles::fasta::record::Definition;
+use noodles::fasta::Record;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+type Job = (usize, String, Vec<Record>);
+
+/// Handed to a splitter so it can pass each finished chunk on to be written
+pub struct ChunkSender<'a> {
+    jobs: SyncSender<Job>,
+    sequence: usize,
+    failed: &'a AtomicBool,
+}
+
+impl ChunkSender<'_> {
+    pub fn send(&mut self, file_name: String, records: Vec<Record>) -> io::Result<()> {
+        // Blocks while --in-flight chunks are already waiting, which is
+        // what keeps memory bounded when the writers fall behind
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(io::Error::other("a chunk failed to write"));
+        }
+        self.jobs
+            .send((self.sequence, file_name, records))
+            .map_err(|_| io::Error::other("chunk writers have stopped"))?;
+        self.sequence += 1;
+        Ok(())
+    }
+}
+
+fn sanitised_record(record: &Record, header: String, issue: String) -> (Record, RenameRow) {
+    // The record under its resolved header, and its rename map row
+    let definition = Definition::new(header, None);
+    let sanitised = Record::new(definition, record.sequence().to_owned());
+    let mut row = RenameRow::new(record, &sanitised);
+    row.issue = issue;
+    (sanitised, row)
+}
+
+/// Handed to a splitter by run_files, so each record can go straight
+/// into the chunk it belongs to as it is read
+pub struct ChunkFiles<'a> {
+    // None for chunks kept by --resume
+    files: Vec<Option<ChunkFile>>,
+    // At most max_open are open at once, the one used least recently is
+    // closed to make room and appended to when it next gets a record
+    max_open: usize,
+    last_used: Vec<usize>,
+    tick: usize,
+    dialect: Option<&'a Dialect>,
+    headers: HeaderTracker,
+    renames: Vec<RenameRow>,
+    rename_map: Option<RenameMap>,
+}
+
+impl ChunkFiles<'_> {
+    pub fn write(&mut self, chunk: usize, record: Record) -> io::Result<()> {
+        self.tick += 1;
+        let Some(file) = self.files[chunk].as_ref() else {
+            return Ok(());
+        };
+        if !file.is_open()
+            && self
+                .files
+                .iter()
+                .flatten()
+                .filter(|file| file.is_open())
+                .count()
+                >= self.max_open
+        {
+            let oldest = (0..self.files.len())
+                .filter(|other| {
+                    self.files[*other]
+                        .as_ref()
+                        .is_some_and(|file| file.is_open())
+                })
+                .min_by_key(|other| self.last_used[*other])
+                .unwrap();
+            self.files[oldest].as_mut().unwrap().close()?;
+        }
+        self.last_used[chunk] = self.tick;
+        let file = self.files[chunk].as_mut().unwrap();
+
+        // Headers are resolved in input order
+        let record = match self.dialect {
+            Some(dialect) => {
+                let header = dialect.sanitise_record(&record.definition().to_string());
+                let (header, issue) = self.headers.resolve(&record, header)?;
+                let (sanitised, mut row) = sanitised_record(&record, header, issue);
+                if let Some(rename_map) = self.rename_map.as_mut() {
+                    row.chunk_file = file.path().to_string();
+                    self.renames.push(row);
+                    if self.renames.len() >= 1024 {
+                        rename_map.add(&self.renames)?;
+                        self.renames.clear();
+                    }
+                }
+                sanitised
+            }
+            None => record,
+        };
+        file.write_record(&record)
+    }
+}
+
+/// Sanitises and writes chunks on --threads workers
+pub struct ChunkWriter<'a> {
+    pub outdir: &'a String,
+    pub dialect: Option<&'a Dialect>,
+    pub rename_file: &'a Path,
+    pub options: &'a SplitOptions,
+}
+
+type Written = (ChunkSummary, Vec<RenameRow>);
+
+impl ChunkWriter<'_> {
+    fn write(
+        &self,
+        sequence: usize,
+        file_name: String,
+        records: Vec<Record>,
+        headers: &InOrder<HeaderTracker>,
+    ) -> io::Result<Written> {
+        // Headers are sanitised in parallel, then checked against the
+        // rest of the run in the order the chunks were sent, so any
+        // _2 suffixes are the same whatever --threads is.
+        // Sanitising keeps a rename map row for every record
+        let mut renames = Vec::new();
+        let records = match self.dialect {
+            Some(dialect) => {
+                let sanitised: Vec<String> = records
+                    .iter()
+                    .map(|record| dialect.sanitise_record(&record.definition().to_string()))
+                    .collect();
+                let resolved = headers.with(sequence, |tracker| {
+                    records
+                        .iter()
+                        .zip(sanitised)
+                        .map(|(record, header)| tracker.resolve(record, header))
+                        .collect::<io::Result<Vec<_>>>()
+                })?;
+                records
+                    .iter()
+                    .zip(resolved)
+                    .map(|(record, (header, issue))| {
+                        let (sanitised, row) = sanitised_record(record, header, issue);
+                        renames.push(row);
+                        sanitised
+                    })
+                    .collect()
+            }
+            None => records,
+        };
+        let chunk = if self.options.dry_run {
+            plan_fasta(self.outdir, file_name, records)?
+        } else {
+            write_fasta(
+                self.outdir,
+                file_name,
+                records,
+                &self.options.existing_files(),
+            )?
+        };
+        for row in renames.iter_mut() {
+            row.chunk_file = chunk.path.clone();
+        }
+        Ok((chunk, renames))
+    }
+
+    fn start(&self, manifest: &Manifest) -> io::Result<(HeaderTracker, Option<RenameMap>)> {
+        // Headers used so far and the rename map, both starting from the
+        // chunks a --resume run keeps
+        let kept = match self.dialect {
+            Some(_) => kept_rows(self.rename_file, &manifest.chunks)?,
+            None => Vec::new(),
+        };
+        let headers = HeaderTracker::new(
+            self.options.on_collision,
+            kept.iter().map(|row| row.new_header.clone()).collect(),
+        );
+        let rename_map = match self.dialect {
+            Some(_) if !self.options.dry_run => Some(RenameMap::create(self.rename_file, &kept)?),
+            _ => None,
+        };
+        Ok((headers, rename_map))
+    }
+
+    pub fn run_files<F>(
+        &self,
+        manifest: &mut Manifest,
+        manifest_file: &Path,
+        file_names: Vec<Option<String>>,
+        produce: F,
+    ) -> io::Result<()>
+    where
+        F: FnOnce(&mut ChunkFiles) -> io::Result<()>,
+    {
+        // For splits where no chunk is complete until the whole input has
+        // been read. Every chunk streams into a temp file of its own, so
+        // memory doesn't grow with the input, and they are renamed into
+        // place and added to the manifest in file order at the end.
+        // A None file name is a chunk --resume keeps, its records are skipped
+        let (headers, rename_map) = self.start(manifest)?;
+        let mut files = Vec::new();
+        for file_name in &file_names {
+            files.push(match file_name {
+                Some(file_name) => Some(ChunkFile::create(
+                    self.outdir,
+                    file_name,
+                    &self.options.existing_files(),
+                    self.options.dry_run,
+                )?),
+                None => None,
+            });
+        }
+        let mut chunks = ChunkFiles {
+            max_open: self.options.max_open.max(1),
+            last_used: vec![0; files.len()],
+            files,
+            tick: 0,
+            dialect: self.dialect,
+            headers,
+            renames: Vec::new(),
+            rename_map,
+        };
+        if let Err(e) = produce(&mut chunks) {
+            for file in chunks.files.into_iter().flatten() {
+                file.discard();
+            }
+            return Err(e);
+        }
+
+        let ChunkFiles {
+            files,
+            headers,
+            renames,
+            rename_map,
+            ..
+        } = chunks;
+        if let Some(mut rename_map) = rename_map {
+            rename_map.add(&renames)?;
+        }
+        for file in files.into_iter().flatten() {
+            let chunk = file.finish()?;
+            if self.options.dry_run {
+                manifest.chunks.push(chunk);
+            } else {
+                manifest.add_chunk(chunk, manifest_file, &self.options.manifest_format)?;
+            }
+        }
+        headers.report();
+        Ok(())
+    }
+
+    pub fn run<F>(
+        &self,
+        manifest: &mut Manifest,
+        manifest_file: &Path,
+        produce: F,
+    ) -> io::Result<()>
+    where
+        F: FnOnce(&mut ChunkSender) -> io::Result<()>,
+    {
+        // Reading stays on this thread, the workers take chunks off a
+        // bounded queue and a collector adds them to the manifest in the
+        // order they were sent, so output is the same whatever --threads is
+        let failed = AtomicBool::new(false);
+        let (headers, mut rename_map) = self.start(manifest)?;
+        let headers = InOrder::new(headers);
+        let (job_tx, job_rx) = mpsc::sync_channel::<Job>(self.options.in_flight.max(1));
+        let job_rx = Mutex::new(job_rx);
+        let (done_tx, done_rx) = mpsc::channel::<(usize, io::Result<Written>)>();
+
+        thread::scope(|scope| {
+            for _ in 0..self.options.threads.max(1) {
+                let job_rx = &job_rx;
+                let headers = &headers;
+                let done_tx = done_tx.clone();
+                scope.spawn(move || loop {
+                    let job = job_rx.lock().unwrap().recv();
+                    let Ok((sequence, file_name, records)) = job else {
+                        break;
+                    };
+                    if done_tx
+                        .send((sequence, self.write(sequence, file_name, records, headers)))
+                        .is_err()
+                    {
+                        break;
+                    }
+                });
+            }
+            drop(done_tx);
+
+            let failed = &failed;
+            let collector = scope.spawn(move || -> io::Result<()> {
+                let mut pending = BTreeMap::new();
+                let mut next = 0;
+                let mut outcome = Ok(());
+                for (sequence, result) in done_rx {
+                    pending.insert(sequence, result);
+                    while let Some(result) = pending.remove(&next) {
+                        next += 1;
+                        if outcome.is_err() {
+                            continue;
+                        }
+                        outcome = result.and_then(|(chunk, renames)| {
+                            if let Some(rename_map) = rename_map.as_mut() {
+                                rename_map.add(&renames)?;
+                            }
+                            if self.options.dry_run {
+                                manifest.chunks.push(chunk);
+                                Ok(())
+                            } else {
+                                manifest.add_chunk(
+                                    chunk,
+                                    manifest_file,
+                                    &self.options.manifest_format,
+                                )
+                            }
+                        });
+                        if outcome.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+                outcome
+            });
+
+            let mut sender = ChunkSender {
+                jobs: job_tx,
+                sequence: 0,
+                failed,
+            };
+            let produced = produce(&mut sender);
+            drop(sender);
+
+            // A write error is more useful than the send error it causes
+            let written = collector.join().unwrap();
+            written.and(produced)
+        })?;
+
+        headers.into_inner().report();
+        Ok(())
+    }
+
+    pub fn finish(
+        &self,
+        manifest: &Manifest,
+        manifest_file: &Path,
+        size_limit: Option<usize>,
+    ) -> io::Result<()> {
+        // A dry run reports the plan in place of writing the manifest.
+        // size_limit is the chunk size in residues, for the oversize list
+        if let Some(annotation) = self.dialect.and_then(|dialect| dialect.annotation.as_ref()) {
+            annotation.report();
+        }
+        if !self.options.dry_run {
+            if self.dialect.is_some() {
+                println!("Rename map written to: {}", self.rename_file.display());
+            }
+            return manifest.finish(manifest_file, &self.options.manifest_format);
+        }
+        let plan = Plan::new(
+            &manifest.settings,
+            self.outdir,
+            &manifest.chunks,
+            size_limit,
+        );
+        plan.report();
+        match &self.options.plan_json {
+            Some(path) => plan.write_json(Path::new(path)),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use super::manifest::ChunkSummary;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// One file a --dry-run would have written
+#[derive(Debug, Serialize)]
+pub struct PlannedFile {
+    pub path: String,
+    pub records: usize,
+    pub residues: usize,
+    pub bytes: u64,
+    pub first_id: String,
+    pub last_id: String,
+}
+
+/// A record with more sequence than the chunk size, it gets a file to itself
+#[derive(Debug, Serialize)]
+pub struct OversizeRecord {
+    pub id: String,
+    pub residues: usize,
+    pub path: String,
+}
+
+/// What a split would do, without any FASTA being written
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    pub settings: String,
+    pub output_directory: String,
+    pub files: usize,
+    pub records: usize,
+    pub residues: usize,
+    pub bytes: u64,
+    pub chunks: Vec<PlannedFile>,
+    pub oversize: Vec<OversizeRecord>,
+}
+
+impl Plan {
+    pub fn new(
+        settings: &str,
+        output_directory: &str,
+        chunks: &[ChunkSummary],
+        size_limit: Option<usize>,
+    ) -> Self {
+        // A record bigger than the limit always ends up alone in its
+        // chunk, so they can be picked out from the chunks themselves
+        let oversize = chunks
+            .iter()
+            .filter(|chunk| {
+                size_limit.is_some_and(|limit| chunk.records == 1 && chunk.residues > limit)
+            })
+            .map(|chunk| OversizeRecord {
+                id: chunk.first_id.clone(),
+                residues: chunk.residues,
+                path: chunk.path.clone(),
+            })
+            .collect();
+
+        Plan {
+            settings: settings.to_string(),
+            output_directory: output_directory.to_string(),
+            files: chunks.len(),
+            records: chunks.iter().map(|chunk| chunk.records).sum(),
+            residues: chunks.iter().map(|chunk| chunk.residues).sum(),
+            bytes: chunks.iter().map(|chunk| chunk.bytes).sum(),
+            chunks: chunks
+                .iter()
+                .map(|chunk| PlannedFile {
+                    path: chunk.path.clone(),
+                    records: chunk.records,
+                    residues: chunk.residues,
+                    bytes: chunk.bytes,
+                    first_id: chunk.first_id.clone(),
+                    last_id: chunk.last_id.clone(),
+                })
+                .collect(),
+            oversize,
+        }
+    }
+
+    pub fn report(&self) {
+        println!("DRY RUN -- nothing has been written");
+        println!(
+            "Would write {} files ({} records, {} residues, {} bytes) to: {}",
+            self.files, self.records, self.residues, self.bytes, self.output_directory
+        );
+        println!("File\tRecords\tResidues\tBytes\tFirst\tLast\tPath");
+        for (file_counter, chunk) in self.chunks.iter().enumerate() {
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                file_counter + 1,
+                chunk.records,
+                chunk.residues,
+                chunk.bytes,
+                chunk.first_id,
+                chunk.last_id,
+                chunk.path
+            );
+        }
+
+        if !self.oversize.is_empty() {
+            println!(
+                "{} records are larger than the chunk size:",
+                self.oversize.len()
+            );
+            println!("Record\tResidues\tPath");
+            for record in &self.oversize {
+                println!("{}\t{}\t{}", record.id, record.residues, record.path);
+            }
+        }
+    }
+
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(path)?;
+        serde_json::to_writer_pretty(&mut file, self)?;
+        writeln!(file)?;
+        println!("Plan written to: {}", path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+use super::detect::looks_nucleotide;
+use super::layout::Layout;
+use super::open_fasta;
+use crate::cli::{DType, SplitOptions};
+use noodles::fasta::Record;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Every reason a record can be dropped, in the order they are checked
+// and reported. A record is only counted against the first it fails
+pub const REASONS: [&str; 10] = [
+    "wrong_alphabet",
+    "too_short",
+    "too_long",
+    "too_ambiguous",
+    "internal_stop",
+    "cds_too_short",
+    "cds_not_multiple_of_3",
+    "cds_bad_start",
+    "cds_bad_stop",
+    "cds_internal_stop",
+];
+
+const STOP_CODONS: [&[u8]; 3] = [b"TAA", b"TAG", b"TGA"];
+
+pub fn qc_report_path(layout: &Layout) -> String {
+    // Next to the manifest, outside the data folder gencsv reads
+    layout.sidecar("qc_report.tsv")
+}
+
+/// The filters asked for on the command line
+#[derive(Debug)]
+pub struct Qc {
+    data_type: DType,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    max_ambiguous: Option<f64>,
+    internal_stops: bool,
+    cds: bool,
+    alphabet: bool,
+}
+
+impl Qc {
+    pub fn from_options(options: &SplitOptions) -> io::Result<Option<Self>> {
+        // None when no filter is set, so nothing extra is read
+        if let Some(fraction) = options.max_ambiguous {
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--max-ambiguous is a fraction between 0 and 1",
+                ));
+            }
+        }
+        let data_type = options.data_type();
+        if options.drop_internal_stops && data_type != DType::Pep {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--drop-internal-stops is for -d pep, use --check-cds for -d cds",
+            ));
+        }
+        if options.check_cds && data_type != DType::Cds {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--check-cds needs -d cds",
+            ));
+        }
+        let qc = Qc {
+            data_type,
+            min_length: options.min_length,
+            max_length: options.max_length,
+            max_ambiguous: options.max_ambiguous,
+            internal_stops: options.drop_internal_stops,
+            cds: options.check_cds,
+            alphabet: options.check_alphabet,
+        };
+        let any = qc.min_length.is_some()
+            || qc.max_length.is_some()
+            || qc.max_ambiguous.is_some()
+            || qc.internal_stops
+            || qc.cds
+            || qc.alphabet;
+        Ok(any.then_some(qc))
+    }
+
+    fn is_ambiguous(residue: u8, nucleotide: bool) -> bool {
+        match nucleotide {
+            true => !b"ACGTU".contains(&residue),
+            false => b"XBZJ".contains(&residue),
+        }
+    }
+
+    pub fn check(&self, record: &Record) -> Option<&'static str> {
+        // The reason the record fails, None if it passes
+        let sequence = record.sequence().as_ref().to_ascii_uppercase();
+        // A trailing stop isn't a residue
+        let residues = match self.data_type {
+            DType::Pep => sequence.strip_suffix(b"*").unwrap_or(&sequence),
+            _ => &sequence[..],
+        };
+
+        // -d other can be either, so each record is taken as it looks
+        let nucleotide = looks_nucleotide(&sequence);
+        if self.alphabet {
+            let wrong = match self.data_type {
+                DType::Pep => nucleotide,
+                // Anything that isn't a sequence letter, gap or stop
+                DType::Other => sequence
+                    .iter()
+                    .any(|c| !c.is_ascii_alphabetic() && !b"*-.".contains(c)),
+                _ => !nucleotide,
+            };
+            if wrong {
+                return Some("wrong_alphabet");
+            }
+        }
+        if self.min_length.is_some_and(|min| residues.len() < min) {
+            return Some("too_short");
+        }
+        if self.max_length.is_some_and(|max| residues.len() > max) {
+            return Some("too_long");
+        }
+        if let Some(max) = self.max_ambiguous {
+            let nucleotide = match self.data_type {
+                DType::Pep => false,
+                DType::Other => nucleotide,
+                _ => true,
+            };
+            let ambiguous = residues
+                .iter()
+                .filter(|residue| Qc::is_ambiguous(**residue, nucleotide))
+                .count();
+            if ambiguous as f64 > max * residues.len() as f64 {
+                return Some("too_ambiguous");
+            }
+        }
+        if self.internal_stops && residues.contains(&b'*') {
+            return Some("internal_stop");
+        }
+        if self.cds {
+            // Room for no more than a start and a stop
+            if sequence.len() < 6 {
+                return Some("cds_too_short");
+            }
+            if sequence.len() % 3 != 0 {
+                return Some("cds_not_multiple_of_3");
+            }
+            if !sequence.starts_with(b"ATG") {
+                return Some("cds_bad_start");
+            }
+            let codons: Vec<&[u8]> = sequence.chunks(3).collect();
+            if !STOP_CODONS.contains(codons.last().unwrap()) {
+                return Some("cds_bad_stop");
+            }
+            if codons[..codons.len() - 1]
+                .iter()
+                .any(|codon| STOP_CODONS.contains(codon))
+            {
+                return Some("cds_internal_stop");
+            }
+        }
+        None
+    }
+}
+
+/// How many records each filter removed
+#[derive(Debug, Default)]
+pub struct QcReport {
+    pub records: usize,
+    pub removed: [usize; REASONS.len()],
+}
+
+impl QcReport {
+    pub fn kept(&self) -> usize {
+        self.records - self.removed.iter().sum::<usize>()
+    }
+
+    pub fn print(&self) {
+        println!("QC: kept {} of {} records", self.kept(), self.records);
+        for (reason, removed) in REASONS.iter().zip(self.removed) {
+            if removed > 0 {
+                println!("  {}: {} removed", reason, removed);
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path, settings: &str) -> io::Result<()> {
+        // Every reason is listed, 0 included, so reports line up across runs
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "#settings\t{}", settings)?;
+        writeln!(writer, "#records\t{}", self.records)?;
+        writeln!(writer, "#kept\t{}", self.kept())?;
+        writeln!(writer, "reason\tremoved")?;
+        for (reason, removed) in REASONS.iter().zip(self.removed) {
+            writeln!(writer, "{}\t{}", reason, removed)?;
+        }
+        writer.flush()
+    }
+}
+
+pub fn qc_filter(
+    options: &SplitOptions,
+    settings: &str,
+    report_file: &Path,
+) -> io::Result<Option<Vec<bool>>> {
+    // A pass over the input before splitting, returns which records pass
+    // by index and writes the QC report. None if no filter is set
+    let Some(qc) = Qc::from_options(options)? else {
+        return Ok(None);
+    };
+    let mut report = QcReport::default();
+    let mut keep = Vec::new();
+    let mut reader = open_fasta(&options.fasta_file)?;
+    for result in reader.records() {
+        let record = result?;
+        report.records += 1;
+        match qc.check(&record) {
+            Some(reason) => {
+                let index = REASONS.iter().position(|r| *r == reason).unwrap();
+                report.removed[index] += 1;
+                keep.push(false);
+            }
+            None => keep.push(true),
+        }
+    }
+
+    report.print();
+    if !options.dry_run {
+        report.write(report_file, settings)?;
+        println!("QC report written to: {}", report_file.display());
+    }
+    Ok(Some(keep))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noodles::fasta::record::{Definition, Sequence};
+
+    fn check_cds(sequence: &str) -> Option<&'static str> {
+        let qc = Qc {
+            data_type: DType::Cds,
+            min_length: None,
+            max_length: None,
+            max_ambiguous: None,
+            internal_stops: false,
+            cds: true,
+            alphabet: false,
+        };
+        let record = Record::new(
+            Definition::new("cds1", None),
+            Sequence::from(sequence.as_bytes().to_vec()),
+        );
+        qc.check(&record)
+    }
+
+    #[test]
+    fn cds_checks() {
+        assert_eq!(check_cds("ATGGCCTAA"), None);
+        assert_eq!(check_cds("atgtaa"), None);
+        assert_eq!(check_cds("ATG"), Some("cds_too_short"));
+        assert_eq!(check_cds("ATGTA"), Some("cds_too_short"));
+        assert_eq!(check_cds(""), Some("cds_too_short"));
+        assert_eq!(check_cds("ATGGCCTA"), Some("cds_not_multiple_of_3"));
+        assert_eq!(check_cds("GCCGCCTAA"), Some("cds_bad_start"));
+        assert_eq!(check_cds("ATGGCCGCC"), Some("cds_bad_stop"));
+        assert_eq!(check_cds("ATGTGAGCCTAA"), Some("cds_internal_stop"));
+    }
+}
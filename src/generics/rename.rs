@@ -0,0 +1,126 @@
+use super::layout::Layout;
+use super::manifest::ChunkSummary;
+use noodles::fasta::Record;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+/// One row of the rename map, how a header was sanitised and where it went
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenameRow {
+    pub original_id: String,
+    pub original_description: String,
+    pub new_header: String,
+    pub chunk_file: String,
+    // Why new_header isn't simply the sanitised header, see --on-collision
+    #[serde(default)]
+    pub issue: String,
+}
+
+impl RenameRow {
+    pub fn new(original: &Record, sanitised: &Record) -> Self {
+        RenameRow {
+            original_id: String::from_utf8_lossy(original.name()).to_string(),
+            original_description: original
+                .description()
+                .map(|description| String::from_utf8_lossy(description).to_string())
+                .unwrap_or_default(),
+            new_header: sanitised.definition().to_string()[1..].to_string(),
+            chunk_file: String::new(),
+            issue: String::new(),
+        }
+    }
+}
+
+pub fn rename_map_path(layout: &Layout) -> String {
+    // Sits next to the manifest, out of gencsv's way
+    layout.sidecar("rename_map.tsv")
+}
+
+pub fn read_rename_map(path: &Path) -> io::Result<Vec<RenameRow>> {
+    // Quoted the way RenameMap writes it, descriptions can hold tabs
+    let mut rdr = csv::ReaderBuilder::new().delimiter(b'\t').from_path(path)?;
+    let mut rows = Vec::new();
+    for result in rdr.deserialize() {
+        rows.push(result?);
+    }
+    Ok(rows)
+}
+
+/// The rename map of a sanitising run, added to as each chunk is written
+pub struct RenameMap {
+    writer: csv::Writer<File>,
+}
+
+pub fn kept_rows(path: &Path, kept: &[ChunkSummary]) -> io::Result<Vec<RenameRow>> {
+    // The rows for the chunks a resumed run keeps
+    if kept.is_empty() || !path.exists() {
+        return Ok(Vec::new());
+    }
+    let kept_paths: HashSet<&String> = kept.iter().map(|chunk| &chunk.path).collect();
+    Ok(read_rename_map(path)?
+        .into_iter()
+        .filter(|row| kept_paths.contains(&row.chunk_file))
+        .collect())
+}
+
+impl RenameMap {
+    pub fn create(path: &Path, kept_rows: &[RenameRow]) -> io::Result<Self> {
+        // Started again each run, with the rows of any chunks kept by --resume
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut map = RenameMap {
+            writer: csv::WriterBuilder::new()
+                .delimiter(b'\t')
+                .has_headers(false)
+                .from_path(path)?,
+        };
+        map.writer.write_record([
+            "original_id",
+            "original_description",
+            "new_header",
+            "chunk_file",
+            "issue",
+        ])?;
+        map.add(kept_rows)?;
+        Ok(map)
+    }
+
+    pub fn add(&mut self, rows: &[RenameRow]) -> io::Result<()> {
+        for row in rows {
+            self.writer.serialize(row)?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::scratch::Scratch;
+
+    #[test]
+    fn descriptions_with_tabs_and_quotes_round_trip() {
+        let dir = Scratch::new("rename");
+        let path = dir.join("pep_rename_map.tsv");
+        let row = RenameRow {
+            original_id: "ENSP0001.1".to_string(),
+            original_description: "pep\tchromosome:GRCh38 \"gene\"\nsymbol:ABC".to_string(),
+            new_header: "protein_id=ENSP0001.1;gene_name=ABC".to_string(),
+            chunk_file: "pep/GRCh38_f1_c100.fa".to_string(),
+            issue: String::new(),
+        };
+        let mut map = RenameMap::create(&path, &[]).unwrap();
+        map.add(&[row.clone(), row.clone()]).unwrap();
+        drop(map);
+
+        let rows = read_rename_map(&path).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].original_description, row.original_description);
+        assert_eq!(rows[1].new_header, row.new_header);
+        assert_eq!(rows[1].chunk_file, row.chunk_file);
+    }
+}
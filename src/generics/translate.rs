@@ -0,0 +1,433 @@
+use super::open_fasta;
+use crate::cli::{DType, SplitOptions};
+use noodles::fasta::record::Sequence;
+use noodles::fasta::Record;
+use std::cell::RefCell;
+use std::io;
+
+// NCBI genetic codes, as the amino acid and start codon lines of
+// https://www.ncbi.nlm.nih.gov/Taxonomy/Utils/wprintgc.cgi
+// Codons are in TCAG order: TTT, TTC, TTA, TTG, TCT ... GGG
+const GENETIC_CODES: [(u8, &str, &str, &str); 22] = [
+    (
+        1,
+        "Standard",
+        "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "---M------**--*----M---------------M----------------------------",
+    ),
+    (
+        2,
+        "Vertebrate Mitochondrial",
+        "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG",
+        "--------------------------------MMMM---------------M------------",
+    ),
+    (
+        3,
+        "Yeast Mitochondrial",
+        "FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "----------------------------------MM----------------------------",
+    ),
+    (
+        4,
+        "Mold, Protozoan, Coelenterate Mitochondrial and Mycoplasma",
+        "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "--MM------**-------M------------MMMM---------------M------------",
+    ),
+    (
+        5,
+        "Invertebrate Mitochondrial",
+        "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG",
+        "---M----------------------------MMMM---------------M------------",
+    ),
+    (
+        6,
+        "Ciliate, Dasycladacean and Hexamita Nuclear",
+        "FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "-----------------------------------M----------------------------",
+    ),
+    (
+        9,
+        "Echinoderm and Flatworm Mitochondrial",
+        "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        "-----------------------------------M---------------M------------",
+    ),
+    (
+        10,
+        "Euplotid Nuclear",
+        "FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "-----------------------------------M----------------------------",
+    ),
+    (
+        11,
+        "Bacterial, Archaeal and Plant Plastid",
+        "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "---M------**--*----M------------MMMM---------------M------------",
+    ),
+    (
+        12,
+        "Alternative Yeast Nuclear",
+        "FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "-------------------M---------------M----------------------------",
+    ),
+    (
+        13,
+        "Ascidian Mitochondrial",
+        "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG",
+        "---M------------------------------MM---------------M------------",
+    ),
+    (
+        14,
+        "Alternative Flatworm Mitochondrial",
+        "FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        "-----------------------------------M----------------------------",
+    ),
+    (
+        16,
+        "Chlorophycean Mitochondrial",
+        "FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "-----------------------------------M----------------------------",
+    ),
+    (
+        21,
+        "Trematode Mitochondrial",
+        "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG",
+        "-----------------------------------M---------------M------------",
+    ),
+    (
+        22,
+        "Scenedesmus obliquus Mitochondrial",
+        "FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "-----------------------------------M----------------------------",
+    ),
+    (
+        23,
+        "Thraustochytrium Mitochondrial",
+        "FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "--------------------------------M--M---------------M------------",
+    ),
+    (
+        24,
+        "Rhabdopleuridae Mitochondrial",
+        "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        "---M---------------M---------------M---------------M------------",
+    ),
+    (
+        25,
+        "Candidate Division SR1 and Gracilibacteria",
+        "FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "---M-------------------------------M---------------M------------",
+    ),
+    (
+        26,
+        "Pachysolen tannophilus Nuclear",
+        "FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "-------------------M---------------M----------------------------",
+    ),
+    (
+        29,
+        "Mesodinium Nuclear",
+        "FFLLSSSSYYYYCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "-----------------------------------M----------------------------",
+    ),
+    (
+        30,
+        "Peritrich Nuclear",
+        "FFLLSSSSYYEECC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG",
+        "-----------------------------------M----------------------------",
+    ),
+    (
+        33,
+        "Cephalodiscidae Mitochondrial",
+        "FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG",
+        "---M-------*-------M---------------M---------------M------------",
+    ),
+];
+
+fn codon_index(codon: &[u8]) -> Option<usize> {
+    // Position of the codon in the TCAG ordered tables, None if it
+    // has anything but ACGT/U in it
+    let mut index = 0;
+    for base in codon {
+        index = index * 4
+            + match base.to_ascii_uppercase() {
+                b'T' | b'U' => 0,
+                b'C' => 1,
+                b'A' => 2,
+                b'G' => 3,
+                _ => return None,
+            };
+    }
+    Some(index)
+}
+
+/// How the translations went, for the report at the end
+#[derive(Debug, Default)]
+pub struct TranslationStats {
+    pub records: usize,
+    pub frameshift: Vec<String>,
+    pub internal_stop: Vec<String>,
+    pub no_start: usize,
+    pub no_stop: usize,
+}
+
+/// Translates CDS records to peptides with one NCBI genetic code
+#[derive(Debug)]
+pub struct Translator {
+    pub table: u8,
+    pub name: &'static str,
+    amino_acids: &'static [u8],
+    starts: &'static [u8],
+    stats: RefCell<TranslationStats>,
+}
+
+impl Translator {
+    pub fn new(table: u8) -> io::Result<Self> {
+        match GENETIC_CODES.iter().find(|code| code.0 == table) {
+            Some((table, name, amino_acids, starts)) => Ok(Translator {
+                table: *table,
+                name,
+                amino_acids: amino_acids.as_bytes(),
+                starts: starts.as_bytes(),
+                stats: RefCell::new(TranslationStats::default()),
+            }),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "No NCBI genetic code {}, the ones known are: {}",
+                    table,
+                    GENETIC_CODES
+                        .iter()
+                        .map(|code| code.0.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )),
+        }
+    }
+
+    pub fn translate(&self, record: Record) -> Record {
+        // The first codon is read as M if it is a start codon in this
+        // table, a final stop is dropped as pep files don't carry one.
+        // Codons with an ambiguous base become X, a trailing partial
+        // codon is left off
+        let sequence = record.sequence().as_ref();
+        let codons: Vec<&[u8]> = sequence.chunks_exact(3).collect();
+        let mut peptide: Vec<u8> = Vec::with_capacity(codons.len());
+        for (position, codon) in codons.iter().enumerate() {
+            let residue = match codon_index(codon) {
+                Some(index) if position == 0 && self.starts[index] == b'M' => b'M',
+                Some(index) => self.amino_acids[index],
+                None => b'X',
+            };
+            peptide.push(residue);
+        }
+
+        let mut stats = self.stats.borrow_mut();
+        let name = String::from_utf8_lossy(record.name()).to_string();
+        stats.records += 1;
+        if sequence.len() % 3 != 0 {
+            stats.frameshift.push(name.clone());
+        }
+        if codons
+            .first()
+            .and_then(|codon| codon_index(codon))
+            .is_none_or(|index| self.starts[index] != b'M')
+        {
+            stats.no_start += 1;
+        }
+        if peptide.last() == Some(&b'*') {
+            peptide.pop();
+        } else {
+            stats.no_stop += 1;
+        }
+        if peptide.contains(&b'*') {
+            stats.internal_stop.push(name);
+        }
+
+        Record::new(record.definition().clone(), Sequence::from(peptide))
+    }
+
+    pub fn report(&self) {
+        let stats = self.stats.borrow();
+        println!(
+            "Translated {} records with genetic code {} ({}): {} not a whole number of codons, {} with internal stops, {} without a start codon, {} without a stop codon",
+            stats.records,
+            self.table,
+            self.name,
+            stats.frameshift.len(),
+            stats.internal_stop.len(),
+            stats.no_start,
+            stats.no_stop
+        );
+        for (issue, names) in [
+            ("Frameshift", &stats.frameshift),
+            ("Internal stop", &stats.internal_stop),
+        ] {
+            for name in names.iter().take(20) {
+                println!("{}\t{}", issue, name);
+            }
+            if names.len() > 20 {
+                println!("{}\t... and {} more", issue, names.len() - 20);
+            }
+        }
+    }
+}
+
+pub fn translated<'a>(
+    records: impl Iterator<Item = io::Result<Record>> + 'a,
+    translator: &'a Option<Translator>,
+) -> impl Iterator<Item = io::Result<Record>> + 'a {
+    // Translate each record when --translate is set, otherwise as they are
+    records.map(move |result| match translator {
+        Some(translator) => result.map(|record| translator.translate(record)),
+        None => result,
+    })
+}
+
+pub fn peptide_sizes(path: &str, table: u8) -> io::Result<Vec<(String, usize)>> {
+    // The header and peptide length of every record, for packing. Whether
+    // the final stop is dropped depends on the last codon, so each record
+    // is translated, on a Translator of its own so these don't count
+    // towards the stats of the real pass
+    let translator = Translator::new(table)?;
+    let mut reader = open_fasta(path)?;
+    let mut sizes = Vec::new();
+    for result in reader.records() {
+        let record = translator.translate(result?);
+        sizes.push((
+            String::from_utf8_lossy(record.name()).to_string(),
+            record.sequence().len(),
+        ));
+    }
+    Ok(sizes)
+}
+
+pub fn load_translator(options: &SplitOptions) -> io::Result<Option<Translator>> {
+    // The Translator for --translate, None if it isn't set
+    if !options.translate {
+        return Ok(None);
+    }
+    if options.data_type() != DType::Cds {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--translate needs CDS input, -d cds",
+        ));
+    }
+    let translator = Translator::new(options.genetic_code)?;
+    println!(
+        "Translating CDS to pep with genetic code {}: {}",
+        translator.table, translator.name
+    );
+    Ok(Some(translator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generics::scratch::Scratch;
+    use noodles::fasta::record::Definition;
+    use std::fs;
+
+    fn peptide(table: u8, cds: &str) -> String {
+        let translator = Translator::new(table).unwrap();
+        let record = Record::new(
+            Definition::new("cds1", None),
+            Sequence::from(cds.as_bytes().to_vec()),
+        );
+        String::from_utf8(translator.translate(record).sequence().as_ref().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn peptide_sizes_match_the_translation() {
+        let dir = Scratch::new("translate-sizes");
+        let input = dir.join("cds.fa");
+        // A final stop, no stop, a trailing partial codon and an internal stop
+        fs::write(
+            &input,
+            ">a\nATGGCCAAATGGTAA\n>b\nATGGCCAAA\n>c\nATGGCCAAATG\n>d\nATGTAAGCCTGA\n",
+        )
+        .unwrap();
+        let sizes = peptide_sizes(input.to_str().unwrap(), 1).unwrap();
+        let expected: Vec<(String, usize)> = [("a", 4), ("b", 3), ("c", 3), ("d", 3)]
+            .iter()
+            .map(|(name, size)| (name.to_string(), *size))
+            .collect();
+        assert_eq!(sizes, expected);
+    }
+
+    #[test]
+    fn every_table_covers_all_64_codons() {
+        for (table, _, amino_acids, starts) in GENETIC_CODES {
+            assert_eq!(amino_acids.len(), 64, "table {}", table);
+            assert_eq!(starts.len(), 64, "table {}", table);
+        }
+    }
+
+    #[test]
+    fn standard_code_drops_the_final_stop() {
+        assert_eq!(peptide(1, "ATGGCCAAATGGTAA"), "MAKW");
+        // RNA is read the same
+        assert_eq!(peptide(1, "AUGGCCAAAUGGUAA"), "MAKW");
+    }
+
+    #[test]
+    fn alternative_starts_only_count_as_the_first_codon() {
+        // GTG is a start in the bacterial code but not the standard one
+        assert_eq!(peptide(11, "GTGAAATAA"), "MK");
+        assert_eq!(peptide(1, "GTGAAATAA"), "VK");
+        // Anywhere else it is valine
+        assert_eq!(peptide(11, "ATGGTGTAA"), "MV");
+        // TTG starts in the standard code, CTG in the alternative yeast one
+        assert_eq!(peptide(1, "TTGAAATAA"), "MK");
+        assert_eq!(peptide(12, "CTGAAATAA"), "MK");
+        assert_eq!(peptide(6, "TTGAAATGA"), "LK");
+    }
+
+    #[test]
+    fn alternative_stops_and_reassigned_stops() {
+        // AGA/AGG are stops in vertebrate mitochondria, TGA is tryptophan
+        assert_eq!(peptide(2, "ATGTGAAAAAGA"), "MWK");
+        assert_eq!(peptide(1, "ATGTGAAAAAGA"), "M*KR");
+        // TAA and TAG are glutamine in the ciliate code
+        assert_eq!(peptide(6, "ATGTAAGCCTGA"), "MQA");
+        // TAG is leucine in the chlorophycean code, TAA still stops
+        assert_eq!(peptide(16, "ATGTAGTAA"), "ML");
+        // TTA is a stop in Thraustochytrium mitochondria
+        assert_eq!(peptide(23, "ATGAAATTA"), "MK");
+    }
+
+    #[test]
+    fn ambiguous_codons_and_partial_codons() {
+        assert_eq!(peptide(1, "ATGNNNGCCTAAGC"), "MXA");
+    }
+
+    #[test]
+    fn stats_count_each_kind_of_problem() {
+        let translator = Translator::new(1).unwrap();
+        for (name, cds) in [
+            ("good", "ATGGCCTAA"),
+            ("frameshift", "ATGGCCTAAG"),
+            ("internal", "ATGTAGGCCTAA"),
+            ("no_start", "GCCGCCTAA"),
+            ("no_stop", "ATGGCCGCC"),
+        ] {
+            let record = Record::new(
+                Definition::new(name, None),
+                Sequence::from(cds.as_bytes().to_vec()),
+            );
+            translator.translate(record);
+        }
+        let stats = translator.stats.borrow();
+        assert_eq!(stats.records, 5);
+        assert_eq!(stats.frameshift, vec!["frameshift"]);
+        assert_eq!(stats.internal_stop, vec!["internal"]);
+        assert_eq!(stats.no_start, 1);
+        assert_eq!(stats.no_stop, 1);
+    }
+
+    #[test]
+    fn unknown_tables_are_refused() {
+        assert!(Translator::new(7).is_err());
+        assert!(Translator::new(0).is_err());
+    }
+}
@@ -5,8 +5,10 @@ use std::io::Error;
 
 use generics::nothing;
 use processors::generate_csv::gencsv_mod::gencsv;
-use processors::sbs::split_by_size_mod::split_file_by_size_electric_boogaloo;
+use processors::prepare_batch::prepare_batch_mod::prepare_batch;
+use processors::restore_headers::restore_headers_mod::restore_headers;
 use processors::split_by_count::split_by_count_mod::split_file_by_count;
+use processors::split_by_group::split_by_group_mod::split_file_by_group;
 use processors::split_by_size::split_by_size_mod::split_file_by_size;
 use processors::yaml_validator::yaml_validator_mod::validate_yaml;
 
@@ -14,46 +16,48 @@ mod cli;
 mod generics;
 mod processors;
 
+// So downstream code can read sanitised headers back into their fields
+pub use generics::header::{Blast, Gff, HeaderFields, HeaderStyle, KeyValue};
+
 pub fn run() -> Result<(), Error> {
     let cli = Cli::parse();
 
-    let _ = match &cli.command {
+    match &cli.command {
         Some(Commands::PrepGenesetBySize {
-            fasta_file,
-            chunk_size,
-            data_type,
-            origin_db,
-            sanitise,
-            outpath,
-        }) => split_file_by_size(
-            fasta_file, chunk_size, data_type, origin_db, sanitise, outpath,
-        ),
-        Some(Commands::PrepGenesetBySize2 {
-            fasta_file,
             chunk_size,
-            data_type,
-            origin_db,
-            sanitise,
-            outpath,
-        }) => split_file_by_size_electric_boogaloo(
-            fasta_file, chunk_size, data_type, origin_db, sanitise, outpath,
-        ),
+            options,
+            strategy,
+            bins,
+        }) => split_file_by_size(chunk_size, strategy, bins, options),
         Some(Commands::PrepGenesetByCount {
-            fasta_file,
             chunk_size,
-            data_type,
-            origin_db,
-            sanitise,
+            n_files,
+            n_files_mode,
+            options,
+        }) => split_file_by_count(chunk_size, n_files, n_files_mode, options),
+        Some(Commands::SplitGroup { options }) => split_file_by_group(options),
+        Some(Commands::GenerateCSV {
+            folder_path,
+            layout,
+        }) => gencsv(folder_path, layout),
+        Some(Commands::PrepareBatch {
+            manifest,
             outpath,
-        }) => split_file_by_count(
-            fasta_file, chunk_size, data_type, origin_db, sanitise, outpath,
-        ),
-        Some(Commands::GenerateCSV { folder_path }) => gencsv(folder_path),
+            layout,
+            jobs,
+            args,
+            dry_run,
+        }) => prepare_batch(manifest, outpath, layout, jobs, args, dry_run),
+        Some(Commands::RestoreHeaders {
+            map_file,
+            input,
+            output,
+            format,
+        }) => restore_headers(map_file, input, output, format),
         Some(Commands::YamlCheck {
             input_yaml,
             out_type,
         }) => validate_yaml(input_yaml, out_type),
         None => nothing(),
-    };
-    Ok(())
+    }
 }
@@ -9,6 +9,7 @@ fn main() {
         eprintln!("Error: {}", e);
         std::process::exit(1);
     } else {
-        println!("Done!");
+        // stderr, so commands writing their output to stdout stay clean
+        eprintln!("Done!");
     }
 }
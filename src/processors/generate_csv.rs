@@ -2,10 +2,11 @@
 /// This is for data tracking for TreeVal
 /// This may be replaced or enhanced with a function to send this to a Google Sheets so the team has an easier way of tracking it all.
 pub mod gencsv_mod {
-    use crate::generics::get_folder_list;
+    use crate::generics::layout::{Template, FOLDER_FIELDS};
+    use crate::generics::DATA_TYPE_FOLDERS;
+    use colored::Colorize;
     use csv::Writer;
-    use std::collections::HashMap;
-    use std::error::Error;
+    use std::collections::BTreeMap;
     use std::io;
     use std::{fs, path::Path, path::PathBuf};
     use walkdir::WalkDir;
@@ -15,38 +16,34 @@ pub mod gencsv_mod {
             .into_iter()
             .filter_map(|e| e.ok())
             .filter(|e| e.file_type().is_file())
+            // Hidden files are temp files left by an interrupted split
+            .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
             .map(|e| e.into_path())
             .collect()
     }
 
-    // Function to convert list to dictionary
-    fn list_2_dict(file_list: &Vec<PathBuf>) -> (HashMap<String, Vec<String>>, String) {
-        let mut file_dict = HashMap::new();
-        let mut org = String::new();
-        for path in file_list {
-            let path_str = path.to_str().unwrap();
-            let path_list: Vec<&str> = path_str.split('/').collect();
-            let file_name = path_list[path_list.len() - 1];
-            if file_name.to_lowercase() != "readme.txt" && file_name.to_lowercase() != "readme" {
-                file_dict.insert(
-                    file_name.to_string(),
-                    vec![
-                        path_list[path_list.len() - 3].to_string(),
-                        path_list[path_list.len() - 2].to_string(),
-                        path_str.to_string(),
-                    ],
-                );
-                org = path_list[path_list.len() - 3].to_string();
-            }
+    fn is_data_folder(folder: &Path, data_type: &str) -> bool {
+        // {type} is one of the built-in data types, or the --label of a
+        // -d other split, which leaves its manifest next to the folder.
+        // {type} needn't be the last folder of the layout
+        if DATA_TYPE_FOLDERS.contains(&data_type) {
+            return true;
         }
-        (file_dict, org)
+        let Some(name) = folder.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        ["tsv", "json"].iter().any(|suffix| {
+            folder
+                .with_file_name(format!("{}_manifest.{}", name, suffix))
+                .exists()
+        })
     }
 
     fn save_data(
-        dict_of_data: HashMap<String, Vec<String>>,
+        dict_of_data: BTreeMap<String, Vec<String>>,
         save_loc: &str,
         org_accession: &str,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> io::Result<()> {
         let save_dir = format!("{}/csv_data", save_loc);
 
         let save_path = format!("{}/csv_data/{}-data.csv", save_loc, org_accession);
@@ -54,11 +51,11 @@ pub mod gencsv_mod {
 
         // Ensure the save directory exists
         if !Path::new(&save_dir).exists() {
-            fs::create_dir_all(&save_dir).unwrap();
+            fs::create_dir_all(&save_dir)?;
         }
 
         if save_path.exists() {
-            fs::remove_file(save_path).unwrap();
+            fs::remove_file(save_path)?;
         }
 
         println!(
@@ -78,49 +75,84 @@ pub mod gencsv_mod {
         Ok(())
     }
 
-    pub fn gencsv(geneset_folder: &String) -> io::Result<()> {
-        let clade_folder = get_folder_list(geneset_folder);
-
-        for clade in clade_folder {
-            let save_clade = clade.clone();
-            let org_folder = get_folder_list(&clade.into_os_string().into_string().unwrap());
-
-            // Filter out the folders ending with csv_data as these are output folders
-            let new_org_folder: Vec<&PathBuf> = org_folder
-                .iter()
-                .filter(|x| !x.ends_with("csv_data"))
-                .collect();
-
-            for org in new_org_folder {
-                let mut master_list = Vec::new();
+    pub fn gencsv(geneset_folder: &String, layout: &str) -> io::Result<()> {
+        // Every folder whose path under geneset_folder fits the layout is
+        // read back into its placeholders, the same --layout the splits used
+        let layout = Template::new(layout, &FOLDER_FIELDS, "--layout")?;
+        if !layout.uses("type") || !(layout.uses("accession") || layout.uses("organism")) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "--layout {} needs {{type}} and one of {{accession}} or {{organism}} to catalogue the data",
+                    layout.text
+                ),
+            ));
+        }
 
-                let accession_folder = get_folder_list(
-                    &<PathBuf as Clone>::clone(org)
-                        .into_os_string()
-                        .into_string()
-                        .unwrap(),
+        // One csv per accession, in the clade folder when the layout starts with one
+        let mut catalogue: BTreeMap<(String, String), BTreeMap<String, Vec<String>>> =
+            BTreeMap::new();
+        let folders = WalkDir::new(geneset_folder)
+            .min_depth(1)
+            .max_depth(layout.depth())
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir());
+        for folder in folders {
+            let Ok(relative) = folder.path().strip_prefix(geneset_folder) else {
+                continue;
+            };
+            // csv_data folders are the output of an earlier run
+            if relative
+                .components()
+                .any(|part| part.as_os_str() == "csv_data")
+            {
+                continue;
+            }
+            let Some(fields) = layout.parse(&relative.to_string_lossy()) else {
+                continue;
+            };
+            if !is_data_folder(folder.path(), &fields["type"]) {
+                println!(
+                    "{} skipping {}, it isn't a data type folder or the output of a -d other split",
+                    "WARNING:".yellow(),
+                    folder.path().display()
                 );
+                continue;
+            }
 
-                for accession in accession_folder {
-                    let data_list = get_folder_list(accession.to_str().unwrap());
-                    for data in data_list {
-                        master_list.push(get_file_list(data.to_str().unwrap()));
-                    }
-
-                    let file_dict: HashMap<String, Vec<String>>;
-                    let orgs: String;
-                    (file_dict, orgs) =
-                        list_2_dict(&master_list.iter().flatten().cloned().collect());
-                    let save_loc = format!(
-                        "{}/{}",
-                        geneset_folder,
-                        save_clade.file_name().unwrap().to_str().unwrap()
+            let org = fields
+                .get("accession")
+                .or(fields.get("organism"))
+                .unwrap()
+                .clone();
+            let save_loc = match fields.get("clade") {
+                Some(clade) if layout.text.starts_with("{clade}/") => {
+                    format!("{}/{}", geneset_folder, clade)
+                }
+                _ => geneset_folder.to_string(),
+            };
+            // Keyed by the whole path, file names alone clash across types
+            // and accessions, e.g. {accession}_f1_c100.fa in pep and cdna
+            let file_dict = catalogue.entry((save_loc, org.clone())).or_default();
+            for path in get_file_list(folder.path().to_str().unwrap()) {
+                let path_str = path.to_str().unwrap();
+                let file_name = path.file_name().unwrap().to_string_lossy();
+                if file_name.to_lowercase() != "readme.txt" && file_name.to_lowercase() != "readme"
+                {
+                    file_dict.insert(
+                        path_str.to_string(),
+                        vec![org.clone(), fields["type"].clone(), path_str.to_string()],
                     );
-                    let _ = save_data(file_dict, &save_loc, &orgs);
                 }
             }
         }
 
+        for ((save_loc, org), file_dict) in catalogue {
+            save_data(file_dict, &save_loc, &org)?;
+        }
+
         Ok(())
     }
 }
@@ -1,5 +1,7 @@
 pub mod generate_csv;
-pub mod sbs;
+pub mod prepare_batch;
+pub mod restore_headers;
 pub mod split_by_count;
+pub mod split_by_group;
 pub mod split_by_size;
 pub mod yaml_validator;
@@ -0,0 +1,463 @@
+/// Run many splits from one manifest, into the clade/organism/accession
+/// tree gencsv reads, then regenerate the CSVs once at the end
+pub mod prepare_batch_mod {
+    use crate::cli::{Cli, Commands, SplitOptions};
+    use crate::generics::data_type_folder;
+    use crate::generics::layout::{Template, FOLDER_FIELDS};
+    use crate::processors::generate_csv::gencsv_mod::gencsv;
+    use crate::processors::split_by_count::split_by_count_mod::split_file_by_count;
+    use crate::processors::split_by_size::split_by_size_mod::split_file_by_size;
+    use clap::Parser;
+    use colored::Colorize;
+    use serde::Deserialize;
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    /// One input in the batch manifest
+    #[derive(Debug, Deserialize)]
+    pub struct BatchRow {
+        pub fasta: String,
+        pub data_type: Option<String>,
+        pub origin_db: Option<String>,
+        pub clade: String,
+        pub organism: String,
+        pub accession: String,
+        // count (splitcount, the default) or size (sizesplit)
+        pub split: Option<String>,
+        pub chunk_size: Option<usize>,
+        pub n_files: Option<usize>,
+        pub strategy: Option<String>,
+        pub bins: Option<usize>,
+        pub label: Option<String>,
+        // Any other split options, e.g. "-c --longest-isoform"
+        pub args: Option<String>,
+    }
+
+    fn invalid(message: String) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    }
+
+    fn read_rows(manifest: &str) -> io::Result<Vec<BatchRow>> {
+        // YAML if the file says so, otherwise TSV with a header row
+        let is_yaml = manifest.ends_with(".yaml") || manifest.ends_with(".yml");
+        if is_yaml {
+            let text = fs::read_to_string(manifest)?;
+            return serde_yaml::from_str(&text)
+                .map_err(|e| invalid(format!("{}: {}", manifest, e)));
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_path(manifest)?;
+        reader
+            .deserialize()
+            .map(|row| row.map_err(|e| invalid(format!("{}: {}", manifest, e))))
+            .collect()
+    }
+
+    impl BatchRow {
+        fn command_line(
+            &self,
+            number: usize,
+            outpath: &str,
+            layout: &str,
+            args: &Option<String>,
+            dry_run: bool,
+        ) -> io::Result<Vec<String>> {
+            // The splitcount/sizesplit command this row stands for, parsed
+            // like any other so it gets the same defaults and checks
+            let command = match self.split.as_deref() {
+                Some("size") => "sizesplit",
+                _ => "splitcount",
+            };
+            let mut line: Vec<String> = vec![
+                "treeval_utils".to_string(),
+                command.to_string(),
+                "-f".to_string(),
+                self.fasta.clone(),
+                "-o".to_string(),
+                outpath.to_string(),
+                "--layout".to_string(),
+                layout.to_string(),
+                "--clade".to_string(),
+                self.clade.clone(),
+                "--organism".to_string(),
+                self.organism.clone(),
+                "--accession".to_string(),
+                self.accession.clone(),
+            ];
+            let flags = [
+                ("-d", self.data_type.clone()),
+                ("-r", self.origin_db.clone()),
+                ("-s", self.chunk_size.map(|size| size.to_string())),
+                ("--n-files", self.n_files.map(|files| files.to_string())),
+                ("--strategy", self.strategy.clone()),
+                ("--bins", self.bins.map(|bins| bins.to_string())),
+                ("--label", self.label.clone()),
+            ];
+            for (flag, value) in flags {
+                if let Some(value) = value {
+                    line.push(flag.to_string());
+                    line.push(value);
+                }
+            }
+            // Split the way a shell would, so quoted values can have spaces
+            for (column, extra) in [("args", &self.args), ("--args", args)] {
+                if let Some(extra) = extra {
+                    let words = shlex::split(extra).ok_or_else(|| {
+                        invalid(format!(
+                            "Row {}: can't split {} {:?}, check its quotes",
+                            number, column, extra
+                        ))
+                    })?;
+                    line.extend(words);
+                }
+            }
+            if dry_run {
+                line.push("--dry-run".to_string());
+            }
+            Ok(line)
+        }
+    }
+
+    fn split_options(cli: &Cli) -> Option<&SplitOptions> {
+        match &cli.command {
+            Some(Commands::PrepGenesetBySize { options, .. })
+            | Some(Commands::PrepGenesetByCount { options, .. }) => Some(options),
+            _ => None,
+        }
+    }
+
+    fn check_rows(
+        rows: &[BatchRow],
+        commands: &[Cli],
+        layout: &Template,
+    ) -> io::Result<Vec<String>> {
+        // Everything is checked before anything runs, so a typo in row
+        // 300 doesn't turn up an hour in. Returns where each row writes
+        let mut targets = Vec::new();
+        let mut seen = HashSet::new();
+        for (number, (row, cli)) in rows.iter().zip(commands).enumerate() {
+            let number = number + 1;
+            for (column, value) in [
+                ("clade", &row.clade),
+                ("organism", &row.organism),
+                ("accession", &row.accession),
+            ] {
+                if value.is_empty() || value.contains('/') || value == "." || value == ".." {
+                    return Err(invalid(format!(
+                        "Row {}: {} {:?} can't be used as a folder name",
+                        number, column, value
+                    )));
+                }
+            }
+            if !Path::new(&row.fasta).exists() {
+                return Err(invalid(format!(
+                    "Row {}: no such FASTA {}",
+                    number, row.fasta
+                )));
+            }
+            if matches!(row.split.as_deref(), Some(split) if split != "count" && split != "size") {
+                return Err(invalid(format!(
+                    "Row {}: split is count or size, not {}",
+                    number,
+                    row.split.as_deref().unwrap_or_default()
+                )));
+            }
+            let Some(options) = split_options(cli) else {
+                return Err(invalid(format!("Row {}: no split command", number)));
+            };
+
+            // Without a data_type the folder isn't known until the split
+            // detects it, so those rows can't be checked for clashes
+            let folder = match options.data_type {
+                Some(_) if options.translate => "pep".to_string(),
+                Some(_) => data_type_folder(options)
+                    .map_err(|e| invalid(format!("Row {}: {}", number, e)))?,
+                None => "detected".to_string(),
+            };
+            let values = HashMap::from([
+                ("clade", row.clade.clone()),
+                ("organism", row.organism.clone()),
+                ("accession", row.accession.clone()),
+                ("type", folder),
+            ]);
+            let target = layout
+                .render(&values)
+                .map_err(|e| invalid(format!("Row {}: {}", number, e)))?;
+            if options.data_type.is_some() && !seen.insert(target.clone()) {
+                return Err(invalid(format!(
+                    "Row {}: {} is already written by an earlier row",
+                    number, target
+                )));
+            }
+            targets.push(target);
+        }
+        Ok(targets)
+    }
+
+    fn run_row(cli: &Cli) -> io::Result<()> {
+        match &cli.command {
+            Some(Commands::PrepGenesetBySize {
+                chunk_size,
+                options,
+                strategy,
+                bins,
+            }) => split_file_by_size(chunk_size, strategy, bins, options),
+            Some(Commands::PrepGenesetByCount {
+                chunk_size,
+                n_files,
+                n_files_mode,
+                options,
+            }) => split_file_by_count(chunk_size, n_files, n_files_mode, options),
+            _ => Err(invalid("not a split command".to_string())),
+        }
+    }
+
+    pub fn prepare_batch(
+        manifest: &str,
+        outpath: &str,
+        layout: &str,
+        jobs: &usize,
+        args: &Option<String>,
+        dry_run: &bool,
+    ) -> io::Result<()> {
+        let rows = read_rows(manifest)?;
+        let template = Template::new(layout, &FOLDER_FIELDS, "--layout")?;
+        let mut commands = Vec::new();
+        for (number, row) in rows.iter().enumerate() {
+            let line = row.command_line(number + 1, outpath, layout, args, *dry_run)?;
+            let mut cli = Cli::try_parse_from(&line).map_err(|e| {
+                invalid(format!(
+                    "Row {}: {}\n{}",
+                    number + 1,
+                    line[1..].join(" "),
+                    e.render()
+                ))
+            })?;
+            // So the manifest records the row's command, not prepare-batch's
+            if let Some(Commands::PrepGenesetBySize { options, .. })
+            | Some(Commands::PrepGenesetByCount { options, .. }) = &mut cli.command
+            {
+                options.command_line = Some(line);
+            }
+            commands.push(cli);
+        }
+        let targets = check_rows(&rows, &commands, &template)?;
+        println!(
+            "Preparing {} inputs from {}, {} at a time",
+            rows.len(),
+            manifest,
+            jobs
+        );
+
+        // Each worker takes the next row until there are none left
+        let next = AtomicUsize::new(0);
+        let results: Vec<Mutex<Option<io::Result<()>>>> =
+            rows.iter().map(|_| Mutex::new(None)).collect();
+        thread::scope(|scope| {
+            for _ in 0..(*jobs).max(1) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= rows.len() {
+                        break;
+                    }
+                    let result = run_row(&commands[index]);
+                    *results[index].lock().unwrap() = Some(result);
+                });
+            }
+        });
+
+        println!("Row\tInput\tOutput\tResult");
+        let mut failed = 0;
+        for (number, ((row, target), result)) in rows.iter().zip(&targets).zip(results).enumerate()
+        {
+            let result = match result.into_inner().unwrap() {
+                Some(Ok(())) => "ok".green().to_string(),
+                Some(Err(e)) => {
+                    failed += 1;
+                    format!("{} {}", "failed:".red(), e)
+                }
+                None => "not run".to_string(),
+            };
+            println!("{}\t{}\t{}\t{}", number + 1, row.fasta, target, result);
+        }
+
+        // The catalogue is only rebuilt once, after every split
+        if !*dry_run && failed < rows.len() {
+            gencsv(&outpath.to_string(), layout)?;
+        }
+        match failed {
+            0 => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "{} of {} inputs failed",
+                failed,
+                rows.len()
+            ))),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::cli::ManifestType;
+        use crate::generics::layout::GENESET_LAYOUT;
+        use crate::generics::manifest::Manifest;
+        use crate::generics::scratch::Scratch;
+
+        fn row(fasta: &str, accession: &str, args: Option<&str>) -> BatchRow {
+            BatchRow {
+                fasta: fasta.to_string(),
+                data_type: Some("pep".to_string()),
+                origin_db: Some("ensembl".to_string()),
+                clade: "mammals".to_string(),
+                organism: "Homo_sapiens".to_string(),
+                accession: accession.to_string(),
+                split: None,
+                chunk_size: Some(2),
+                n_files: None,
+                strategy: None,
+                bins: None,
+                label: None,
+                args: args.map(String::from),
+            }
+        }
+
+        fn check(rows: &[BatchRow]) -> io::Result<Vec<String>> {
+            let commands: Vec<Cli> = rows
+                .iter()
+                .enumerate()
+                .map(|(number, row)| {
+                    let line = row
+                        .command_line(number + 1, "out", GENESET_LAYOUT, &None, false)
+                        .unwrap();
+                    Cli::try_parse_from(line).unwrap()
+                })
+                .collect();
+            let layout = Template::new(GENESET_LAYOUT, &FOLDER_FIELDS, "--layout")?;
+            check_rows(rows, &commands, &layout)
+        }
+
+        #[test]
+        fn rows_become_split_commands() {
+            let mut size = row("a.fa", "GCA_1.1", Some("-c --label 'two words'"));
+            size.split = Some("size".to_string());
+            size.strategy = Some("first-fit-decreasing".to_string());
+            let args = Some("--threads 2".to_string());
+            let line = size
+                .command_line(1, "out", GENESET_LAYOUT, &args, true)
+                .unwrap();
+            assert_eq!(
+                line[..4],
+                ["treeval_utils", "sizesplit", "-f", "a.fa"].map(String::from)
+            );
+            assert!(line
+                .windows(2)
+                .any(|pair| pair == ["--strategy", "first-fit-decreasing"]));
+            // Row args, then --args for every row, then --dry-run
+            assert_eq!(
+                line[line.len() - 6..],
+                ["-c", "--label", "two words", "--threads", "2", "--dry-run"].map(String::from)
+            );
+
+            let line = row("a.fa", "GCA_1.1", None)
+                .command_line(1, "out", GENESET_LAYOUT, &None, false)
+                .unwrap();
+            assert_eq!(line[1], "splitcount");
+            assert!(!line.contains(&"--dry-run".to_string()));
+        }
+
+        #[test]
+        fn bad_quoting_names_the_row() {
+            let error = row("a.fa", "GCA_1.1", Some("--label 'open"))
+                .command_line(3, "out", GENESET_LAYOUT, &None, false)
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            assert!(error.to_string().starts_with("Row 3: can't split args"));
+
+            let args = Some("--label \"open".to_string());
+            let error = row("a.fa", "GCA_1.1", None)
+                .command_line(4, "out", GENESET_LAYOUT, &args, false)
+                .unwrap_err();
+            assert!(error.to_string().starts_with("Row 4: can't split --args"));
+        }
+
+        #[test]
+        fn rows_are_checked_before_anything_runs() {
+            let dir = Scratch::new("batch-check");
+            let fasta = dir.join("a.fa");
+            fs::write(&fasta, ">a\nMKV\n").unwrap();
+            let fasta = fasta.to_str().unwrap();
+
+            let targets =
+                check(&[row(fasta, "GCA_1.1", None), row(fasta, "GCA_2.1", None)]).unwrap();
+            assert_eq!(
+                targets,
+                vec![
+                    "mammals/Homo_sapiens/GCA_1.1/pep",
+                    "mammals/Homo_sapiens/GCA_2.1/pep"
+                ]
+            );
+
+            let error =
+                check(&[row(fasta, "GCA_1.1", None), row(fasta, "GCA_1.1", None)]).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Row 2: mammals/Homo_sapiens/GCA_1.1/pep is already written by an earlier row"
+            );
+            // Without a data type the folder isn't known, so no clash
+            let mut detected = row(fasta, "GCA_1.1", None);
+            detected.data_type = None;
+            assert!(check(&[detected, row(fasta, "GCA_1.1", None)]).is_ok());
+
+            let error = check(&[row(fasta, "GCA_1.1", None), row(fasta, "..", None)]).unwrap_err();
+            assert!(error.to_string().starts_with("Row 2: accession \"..\""));
+            let missing = dir.join("missing.fa");
+            let error = check(&[row(missing.to_str().unwrap(), "GCA_1.1", None)]).unwrap_err();
+            assert!(error.to_string().starts_with("Row 1: no such FASTA"));
+        }
+
+        #[test]
+        fn manifests_record_the_row_command() {
+            let dir = Scratch::new("batch-command");
+            fs::create_dir(dir.join("my genes")).unwrap();
+            let fasta = dir.join("my genes/a.fa");
+            fs::write(&fasta, ">a\nMKV\n>b\nMKVL\n>c\nMK\n").unwrap();
+            let batch = dir.join("batch.tsv");
+            fs::write(
+                &batch,
+                format!(
+                    "fasta\tdata_type\torigin_db\tclade\torganism\taccession\tchunk_size\n{}\tpep\tensembl\tmammals\tHomo_sapiens\tGCA_1.1\t2\n",
+                    fasta.display()
+                ),
+            )
+            .unwrap();
+            let outpath = dir.join("out");
+            let outpath = outpath.to_str().unwrap();
+            prepare_batch(
+                batch.to_str().unwrap(),
+                outpath,
+                GENESET_LAYOUT,
+                &1,
+                &None,
+                &false,
+            )
+            .unwrap();
+
+            let manifest_file = dir.join("out/mammals/Homo_sapiens/GCA_1.1/pep_manifest.tsv");
+            let manifest = Manifest::read(&manifest_file, &ManifestType::Tsv).unwrap();
+            assert_eq!(manifest.command_line[..2], ["treeval_utils", "splitcount"]);
+            assert!(manifest
+                .command_line
+                .windows(2)
+                .any(|pair| pair == ["-f", fasta.to_str().unwrap()]));
+            assert_eq!(manifest.chunks.len(), 2);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+/// Undo --clean_headers on files made downstream from the sanitised chunks,
+/// using the rename map a sanitising split writes
+pub mod restore_headers_mod {
+    use crate::cli::RestoreFormat;
+    use crate::generics::open_text;
+    use crate::generics::rename::read_rename_map;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::{self, BufRead, BufWriter, Write};
+    use std::path::Path;
+
+    fn guess_format(input: &str) -> io::Result<RestoreFormat> {
+        // From the suffix, ignoring a trailing .gz
+        let name = input.trim_end_matches(".gz").to_lowercase();
+        let suffix = name.rsplit('.').next().unwrap_or_default();
+        match suffix {
+            "fa" | "fasta" | "faa" | "fna" | "pep" | "cds" => Ok(RestoreFormat::Fasta),
+            "bed" => Ok(RestoreFormat::Bed),
+            "paf" => Ok(RestoreFormat::Paf),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Can't tell the format of {}, set --format", input),
+            )),
+        }
+    }
+
+    pub fn restore_headers(
+        map_file: &str,
+        input: &str,
+        output: &Option<String>,
+        format: &Option<RestoreFormat>,
+    ) -> io::Result<()> {
+        let format = match format {
+            Some(format) => *format,
+            None => guess_format(input)?,
+        };
+
+        // new header -> original, a sanitised header shared by more than
+        // one record can't be undone, the first is used and it is counted
+        let mut originals: HashMap<String, (String, String)> = HashMap::new();
+        let mut ambiguous = 0;
+        for row in read_rename_map(Path::new(map_file))? {
+            if originals.contains_key(&row.new_header) {
+                ambiguous += 1;
+                continue;
+            }
+            originals.insert(row.new_header, (row.original_id, row.original_description));
+        }
+        // Progress goes to stderr so stdout can be the restored file
+        eprintln!(
+            "Loaded {} renamed headers from: {}",
+            originals.len(),
+            map_file
+        );
+        if ambiguous > 0 {
+            eprintln!(
+                "{} rows share a new header with an earlier row, the first original is used for those",
+                ambiguous
+            );
+        }
+
+        let reader = open_text(input)?;
+        let mut writer: Box<dyn Write> = match output {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(BufWriter::new(io::stdout())),
+        };
+
+        // The name columns of each format, BED chrom and name, PAF
+        // query and target. Track, browser and comment lines are left be
+        let columns: &[usize] = match format {
+            RestoreFormat::Fasta => &[],
+            RestoreFormat::Bed => &[0, 3],
+            RestoreFormat::Paf => &[0, 5],
+        };
+        let mut restored = 0;
+        let mut missing = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let new_line = match format {
+                RestoreFormat::Fasta => match line.strip_prefix('>') {
+                    // A FASTA gets the whole original definition line back
+                    Some(header) => match originals.get(header) {
+                        Some((id, description)) if description.is_empty() => {
+                            restored += 1;
+                            format!(">{}", id)
+                        }
+                        Some((id, description)) => {
+                            restored += 1;
+                            format!(">{} {}", id, description)
+                        }
+                        None => {
+                            missing += 1;
+                            line
+                        }
+                    },
+                    None => line,
+                },
+                _ if line.starts_with('#')
+                    || line.starts_with("track")
+                    || line.starts_with("browser") =>
+                {
+                    line
+                }
+                _ => {
+                    // BED and PAF names are single tokens, so only the ID goes back
+                    let mut fields: Vec<&str> = line.split('\t').collect();
+                    let mut found = false;
+                    for column in columns {
+                        if let Some((id, _)) =
+                            fields.get(*column).and_then(|field| originals.get(*field))
+                        {
+                            fields[*column] = id;
+                            found = true;
+                        }
+                    }
+                    if found {
+                        restored += 1;
+                    } else {
+                        missing += 1;
+                    }
+                    fields.join("\t")
+                }
+            };
+            writeln!(writer, "{}", new_line)?;
+        }
+        writer.flush()?;
+
+        eprintln!(
+            "Restored {} lines, {} had no name in the rename map and were left as they are",
+            restored, missing
+        );
+        Ok(())
+    }
+}
@@ -1,99 +1,276 @@
 pub mod split_by_count_mod {
-    use crate::cli::{DType, OriginType};
-    use crate::generics::{sanitise_header, write_fasta};
+    use crate::cli::{NFilesMode, OriginType, SplitOptions};
+    use crate::generics::dedupe::{dedupe, dedupe_table_path};
+    use crate::generics::detect::detect_types;
+    use crate::generics::dialect::load_sanitiser;
+    use crate::generics::isoform::{kept_records, longest_isoforms};
+    use crate::generics::layout::{output_name, Layout, COUNT_FILE_NAME};
+    use crate::generics::manifest::{manifest_path, Manifest, Resume};
+    use crate::generics::pipeline::ChunkWriter;
+    use crate::generics::qc::{qc_filter, qc_report_path};
+    use crate::generics::rename::rename_map_path;
+    use crate::generics::translate::{load_translator, translated};
+    use crate::generics::{data_type_folder, open_fasta, open_indexed_fasta, open_text};
+    use colored::Colorize;
     use compare::{natural, Compare};
-    use noodles::fasta::{self, Record};
+    use noodles::fasta::Record;
     use std::cmp::Ordering;
-    use std::io;
-    use std::{fs::File, io::BufReader, path::Path};
-
-    fn fix_head(records: Record, sanitise: bool, origin: &str) -> Record {
-        // Taker a Record and sanitise the header
-        // recombine into a new Record
-        if sanitise {
-            let header = sanitise_header(records.definition(), origin);
-            let definition = fasta::record::Definition::new(header, None);
-            let seq = records.sequence().to_owned();
-
-            fasta::Record::new(definition, seq)
-        } else {
-            records.to_owned()
+    use std::io::{self, BufRead};
+    use std::path::Path;
+
+    fn count_records(fasta_file: &str, keep: &Option<Vec<bool>>) -> io::Result<usize> {
+        // Records that will be written, for --n-files. Free if QC or
+        // dedupe already read the input, otherwise from an up to date
+        // .fai, otherwise a pass counting header lines
+        if let Some(keep) = keep {
+            return Ok(keep.iter().filter(|kept| **kept).count());
+        }
+        if let Some(reader) = open_indexed_fasta(fasta_file)? {
+            let count = reader.index().as_ref().len();
+            println!("Record count from {}.fai: {}", fasta_file, count);
+            return Ok(count);
+        }
+        let mut count = 0;
+        for line in open_text(fasta_file)?.split(b'\n') {
+            if line?.first() == Some(&b'>') {
+                count += 1;
+            }
         }
+        println!("Counted {} records in {}", count, fasta_file);
+        Ok(count)
+    }
+
+    fn file_sizes(total: usize, n_files: usize) -> Vec<usize> {
+        // Contiguous --n-files, the first total % n_files files take one
+        // record more than the rest. Never more files than records
+        let n_files = n_files.min(total);
+        (0..n_files)
+            .map(|file| total / n_files + usize::from(file < total % n_files))
+            .collect()
+    }
+
+    fn check_read(sizes: &[usize], records_read: usize) -> io::Result<()> {
+        // --n-files should write exactly the records it counted
+        let counted = sizes.iter().sum::<usize>();
+        if counted == records_read {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} records were counted for --n-files but {} were read, is the .fai out of date?",
+                counted, records_read
+            ),
+        ))
     }
 
     pub fn split_file_by_count(
-        fasta_file: &String,
         chunk_size: &usize,
-        data_type: &DType,
-        origin: &OriginType,
-        sanitise: &bool,
-        outpath: &String,
+        n_files: &Option<usize>,
+        n_files_mode: &NFilesMode,
+        options: &SplitOptions,
     ) -> io::Result<()> {
-        let data_type = match data_type {
-            DType::Pep => "pep",
-            DType::Cdna => "cdna",
-            DType::Cds => "cds",
-            DType::Rna => "rna",
-            DType::Other => {
-                panic!("NOT PLANNED FOR")
-            }
+        if *n_files == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--n-files needs to be 1 or more",
+            ));
+        }
+        let round_robin = n_files.is_some() && *n_files_mode == NFilesMode::RoundRobin;
+        if round_robin && options.resume {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--resume can't be used with --n-files-mode round-robin, every file is only written at the end",
+            ));
+        }
+        // Fill in or check -d and -r from a sample of the input
+        let options = &detect_types(options)?;
+        let fasta_file = &options.fasta_file;
+        // pep, cdna, rna, cds or the --label of -d other
+        let data_type_name = data_type_folder(options)?;
+        // --translate writes the peptides into the pep tree
+        let translator = load_translator(options)?;
+        let data_type = if translator.is_some() {
+            "pep"
+        } else {
+            &data_type_name
         };
 
-        let origin_db = match origin {
+        let origin_db = match options.origin_db() {
             OriginType::Other => "na",
             OriginType::Ensembl => "ensembl",
             OriginType::Ncbi => "ncbi",
         };
 
-        let path_obj = Path::new(fasta_file);
-        let grab_name = path_obj.file_name().unwrap();
-        let actual_list: Vec<&str> = grab_name.to_str().unwrap().split('.').collect();
-        let actual_name = actual_list[0];
+        // --accession, or the input file name without its suffixes
+        let actual_name = &output_name(options);
+
+        let mut layout = Layout::new(
+            options,
+            actual_name,
+            data_type,
+            *chunk_size,
+            COUNT_FILE_NAME,
+        )?;
+        match n_files {
+            Some(n_files) => println!(
+                "Fasta file for processing: {}\nNumber of files: {} ({:?})\nData is from: {}",
+                fasta_file, n_files, n_files_mode, &origin_db,
+            ),
+            None => println!(
+                "Fasta file for processing: {}\nNumber of records per file: {}\nData is from: {}",
+                fasta_file, chunk_size, &origin_db,
+            ),
+        }
 
-        let new_outpath = format!("{}/{}/{}/", outpath, actual_name, data_type);
-        println!(
-            "Fasta file for processing: {}\nNumber of records per file: {}\nData is from: {}",
-            fasta_file, chunk_size, &origin_db,
+        let manifest_file = manifest_path(&layout, &options.manifest_format);
+        let manifest_file = Path::new(&manifest_file);
+        let settings = format!(
+            "splitcount chunk_size={} n_files={:?} n_files_mode={:?} {}",
+            chunk_size,
+            n_files,
+            n_files_mode,
+            options.settings()
+        );
+        let resume = if options.resume {
+            Resume::load(manifest_file, &options.manifest_format, &settings)?
+        } else {
+            Resume::default()
+        };
+        let mut manifest = Manifest::new(
+            options.command_line.clone(),
+            settings,
+            resume.chunks.clone(),
         );
 
         // Header counter
         let mut counter: usize = 0;
-        let mut file_counter: u16 = 1;
+        let mut file_counter: usize = resume.chunks.len() + 1;
 
-        // Remove the file suffix from the file name
-        let file_name: Vec<&str> = actual_name.split('.').collect();
+        // Which records survive QC, --longest-isoform and --dedupe, each needs a pass of its own
+        let qc_file = qc_report_path(&layout);
+        let qc = qc_filter(options, &manifest.settings, Path::new(&qc_file))?;
+        let keep = if options.longest_isoform {
+            Some(longest_isoforms(options, &qc)?)
+        } else {
+            qc
+        };
+        let dedupe_file = dedupe_table_path(&layout);
+        let keep = dedupe(options, keep, Path::new(&dedupe_file))?;
 
-        // Open the fasta file
-        let mut reader = File::open(fasta_file)
-            .map(BufReader::new)
-            .map(fasta::Reader::new)
-            .unwrap();
+        // How many records each --n-files file gets, {size} in the file
+        // names becomes the most any of them hold
+        let sizes = match n_files {
+            Some(n_files) => {
+                let total = count_records(fasta_file, &keep)?;
+                if total < *n_files {
+                    println!(
+                        "{} only {} records, so only {} files rather than {}",
+                        "WARNING:".yellow(),
+                        total,
+                        total,
+                        n_files
+                    );
+                }
+                let sizes = file_sizes(total, *n_files);
+                layout.set_size(sizes.first().copied().unwrap_or_default());
+                Some(sizes)
+            }
+            None => None,
+        };
+
+        // Open the fasta file, plain, gzip or bgzip
+        let mut reader = open_fasta(fasta_file)?;
 
         // Create a Record List
         let mut record_list: Vec<Record> = Vec::new();
 
-        // Easily going to be a better way of doing this!
-        for result in reader.records() {
-            let record = result.unwrap();
-            counter += 1;
+        // Headers are sanitised and chunks written by the ChunkWriter threads
+        let dialect = load_sanitiser(options)?;
+        let rename_file = rename_map_path(&layout);
+        let writer = ChunkWriter {
+            outdir: &layout.outdir,
+            dialect: dialect.as_ref(),
+            rename_file: Path::new(&rename_file),
+            options,
+        };
+        if round_robin {
+            // Every file takes records until the end of the input, so each
+            // one streams into a temp file of its own and is renamed into
+            // place once the input has been read
+            let sizes = sizes.as_ref().unwrap();
+            let file_names = sizes
+                .iter()
+                .enumerate()
+                .map(|(file, size)| Some(layout.file_name(file + 1, *size)))
+                .collect();
+            writer.run_files(&mut manifest, manifest_file, file_names, |files| {
+                let mut records_read = 0;
+                for result in translated(kept_records(reader.records(), &keep), &translator) {
+                    let record = result?;
+                    if records_read == sizes.iter().sum::<usize>() {
+                        return check_read(sizes, records_read + 1);
+                    }
+                    files.write(records_read % sizes.len(), record)?;
+                    records_read += 1;
+                }
+                check_read(sizes, records_read)
+            })?;
+        } else {
+            writer.run(&mut manifest, manifest_file, |chunks| {
+                // Easily going to be a better way of doing this!
+                let mut records_read = 0;
+                for (index, result) in
+                    translated(kept_records(reader.records(), &keep), &translator).enumerate()
+                {
+                    let record = result?;
+                    records_read += 1;
 
-            let final_rec = fix_head(record, *sanitise, origin_db);
-            record_list.push(final_rec);
+                    // Already written by the run being resumed
+                    if index < resume.records() {
+                        if resume.is_boundary(index) {
+                            resume.check(index, &record, dialect.as_ref())?;
+                        }
+                        continue;
+                    }
+                    counter += 1;
+                    record_list.push(record);
 
-            let cmp = natural();
-            let compared = cmp.compare(&counter, chunk_size);
-            if compared == Ordering::Equal {
-                let file_name = format!("{}_f{}_c{}.fa", file_name[0], file_counter, &chunk_size);
+                    // -s, or this file's share of --n-files
+                    let limit = match &sizes {
+                        Some(sizes) => sizes.get(file_counter - 1).copied().unwrap_or(usize::MAX),
+                        None => *chunk_size,
+                    };
+                    let cmp = natural();
+                    let compared = cmp.compare(&counter, &limit);
+                    if compared == Ordering::Equal {
+                        let file_name = layout.file_name(file_counter, record_list.len());
 
-                let _ = write_fasta(&new_outpath, file_name, record_list);
-                file_counter += 1;
-                counter = 0;
-                record_list = Vec::new();
-            }
+                        chunks.send(file_name, record_list)?;
+                        file_counter += 1;
+                        counter = 0;
+                        record_list = Vec::new();
+                    }
+                }
+
+                // Only write the remainder if there is one, otherwise an input
+                // which divides evenly leaves an empty file at the end
+                if !record_list.is_empty() {
+                    let file_name = layout.file_name(file_counter, record_list.len());
+                    chunks.send(file_name, record_list)?;
+                }
+                match &sizes {
+                    Some(sizes) => check_read(sizes, records_read),
+                    None => Ok(()),
+                }
+            })?;
+        }
+
+        if let Some(translator) = &translator {
+            translator.report();
         }
 
-        let file_name = format!("{}_f{}_c{}.fa", file_name[0], file_counter, &chunk_size,);
-        let _ = write_fasta(&new_outpath, file_name, record_list);
-        Ok(())
+        // Chunks are a record count, so no record can be too big for one
+        writer.finish(&manifest, manifest_file, None)
     }
 }
@@ -0,0 +1,508 @@
+/// Split a FASTA into one file per group, the group of each record taken
+/// from its header with a regex or a dialect field, or from an ID table
+pub mod split_by_group_mod {
+    use crate::cli::GroupOptions;
+    use crate::generics::annotation::Annotation;
+    use crate::generics::dialect::Dialect;
+    use crate::generics::layout::{base_name, Template};
+    use crate::generics::open_fasta;
+    use colored::Colorize;
+    use noodles::fasta::{self, Record};
+    use regex::Regex;
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, BufRead, BufReader, BufWriter, Write};
+    use std::path::{Path, PathBuf};
+
+    fn invalid(message: String) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    }
+
+    /// How the group of a record is found
+    enum Grouping {
+        Regex(Regex),
+        Field(String, Dialect),
+        Map(HashMap<String, String>),
+    }
+
+    impl Grouping {
+        fn group(&self, record: &Record) -> Option<String> {
+            let header = record.definition().to_string();
+            match self {
+                // The named capture group, otherwise the whole match
+                Grouping::Regex(re) => re.captures(&header).map(|caps| {
+                    caps.name("group")
+                        .unwrap_or_else(|| caps.get(0).unwrap())
+                        .as_str()
+                        .to_string()
+                }),
+                Grouping::Field(field, dialect) => {
+                    let fields = dialect.record_fields(&header);
+                    match field.as_str() {
+                        // seqid:start-end(strand)
+                        "chromosome" => fields
+                            .location
+                            .as_ref()
+                            .and_then(|location| location.rsplit_once(':'))
+                            .map(|(seqid, _)| seqid.to_string()),
+                        field => fields.get(field).cloned(),
+                    }
+                }
+                // By ID, then without a version
+                Grouping::Map(map) => {
+                    let id = String::from_utf8_lossy(record.name()).to_string();
+                    map.get(&id)
+                        .or_else(|| id.rsplit_once('.').and_then(|(base, _)| map.get(base)))
+                        .cloned()
+                }
+            }
+        }
+    }
+
+    fn read_map(path: &str) -> io::Result<HashMap<String, String>> {
+        // ID<TAB>group, # lines are comments
+        let mut map = HashMap::new();
+        for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('\t') {
+                Some((id, group)) if !group.trim().is_empty() => {
+                    map.insert(id.trim().to_string(), group.trim().to_string());
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "{} line {}: expected ID<TAB>group",
+                        path,
+                        number + 1
+                    )))
+                }
+            }
+        }
+        println!("Loaded {} IDs from: {}", map.len(), path);
+        Ok(map)
+    }
+
+    fn safe_name(group: &str) -> String {
+        // Anything that could break a path becomes _
+        let name: String = group
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        match name.strip_prefix('.') {
+            Some(rest) => format!("_{}", rest),
+            None => name,
+        }
+    }
+
+    #[derive(Default)]
+    struct GroupFile {
+        file_name: String,
+        records: usize,
+        residues: usize,
+        last_used: usize,
+    }
+
+    /// Files for each group, written to hidden temp files and renamed into
+    /// place at the end. At most max_open are open at once, the one used
+    /// least recently is closed to make room and appended to if it comes up again
+    struct GroupWriter {
+        outdir: PathBuf,
+        max_open: usize,
+        force: bool,
+        groups: BTreeMap<String, GroupFile>,
+        names: HashSet<String>,
+        open: HashMap<String, fasta::Writer<BufWriter<File>>>,
+        tick: usize,
+        reopened: usize,
+    }
+
+    impl GroupWriter {
+        fn temp_path(&self, file_name: &str) -> PathBuf {
+            self.outdir.join(format!(".{}.tmp", file_name))
+        }
+
+        fn write(
+            &mut self,
+            group: &str,
+            record: &Record,
+            file_name: impl Fn(&str) -> String,
+        ) -> io::Result<()> {
+            self.tick += 1;
+            if !self.groups.contains_key(group) {
+                // Two groups can come out the same once made safe for a
+                // file name, the later one gets a number on the end
+                let safe = safe_name(group);
+                let mut name = file_name(&safe);
+                let mut suffix = 1;
+                while self.names.contains(&name) {
+                    suffix += 1;
+                    name = file_name(&format!("{}_{}", safe, suffix));
+                }
+                // A temp file is left by a run that died, or is in use by
+                // one still going
+                for path in [self.outdir.join(&name), self.temp_path(&name)] {
+                    if path.exists() && !self.force {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!(
+                                "{} already exists, use --force to overwrite it",
+                                path.display()
+                            ),
+                        ));
+                    }
+                }
+                self.names.insert(name.clone());
+                self.groups.insert(
+                    group.to_string(),
+                    GroupFile {
+                        file_name: name,
+                        ..Default::default()
+                    },
+                );
+            }
+
+            if !self.open.contains_key(group) {
+                if self.open.len() >= self.max_open {
+                    let oldest = self
+                        .open
+                        .keys()
+                        .min_by_key(|open| self.groups[*open].last_used)
+                        .unwrap()
+                        .clone();
+                    let mut writer = self.open.remove(&oldest).unwrap();
+                    writer.get_mut().flush()?;
+                }
+                let entry = &self.groups[group];
+                let temp_path = self.temp_path(&entry.file_name);
+                // New groups start a file, closed ones carry on where they were
+                let file = if entry.records == 0 {
+                    File::create(&temp_path)?
+                } else {
+                    self.reopened += 1;
+                    OpenOptions::new().append(true).open(&temp_path)?
+                };
+                self.open
+                    .insert(group.to_string(), fasta::Writer::new(BufWriter::new(file)));
+            }
+
+            self.open.get_mut(group).unwrap().write_record(record)?;
+            let entry = self.groups.get_mut(group).unwrap();
+            entry.records += 1;
+            entry.residues += record.sequence().len();
+            entry.last_used = self.tick;
+            Ok(())
+        }
+
+        fn discard(mut self) {
+            // After an error, so no temp files are left in -o
+            self.open.clear();
+            for entry in self.groups.values() {
+                let _ = fs::remove_file(self.temp_path(&entry.file_name));
+            }
+        }
+
+        fn finish(mut self) -> io::Result<BTreeMap<String, GroupFile>> {
+            for (_, mut writer) in self.open.drain() {
+                writer.get_mut().flush()?;
+            }
+            for entry in self.groups.values() {
+                fs::rename(
+                    self.temp_path(&entry.file_name),
+                    self.outdir.join(&entry.file_name),
+                )?;
+            }
+            Ok(self.groups)
+        }
+    }
+
+    pub fn split_file_by_group(options: &GroupOptions) -> io::Result<()> {
+        let GroupOptions {
+            fasta_file,
+            outpath,
+            regex,
+            field,
+            map,
+            dialect,
+            rules,
+            annotation,
+            ungrouped,
+            accession,
+            file_name,
+            max_open,
+            force,
+        } = options;
+        if *max_open == 0 {
+            return Err(invalid("--max-open needs to be 1 or more".to_string()));
+        }
+        let template = Template::new(file_name, &["accession", "group"], "--file-name")?;
+        if template.depth() > 1 || !template.uses("group") {
+            return Err(invalid(format!(
+                "--file-name {} needs {{group}} and can't have folders in it",
+                file_name
+            )));
+        }
+        let accession = accession.clone().unwrap_or_else(|| base_name(fasta_file));
+        let name_for = |group: &str| {
+            let values = HashMap::from([
+                ("accession", accession.clone()),
+                ("group", group.to_string()),
+            ]);
+            template.render(&values)
+        };
+        // Fails here rather than part way through if --accession won't do
+        name_for("group")?;
+
+        let grouping = match (regex, field, map) {
+            (Some(pattern), _, _) => Grouping::Regex(
+                Regex::new(pattern).map_err(|e| invalid(format!("--regex: {}", e)))?,
+            ),
+            (_, Some(field), _) => {
+                let mut found = match dialect {
+                    Some(name) => Dialect::load(name, rules.as_deref())?,
+                    None => Dialect::annotation_only()?,
+                };
+                if dialect.is_none() && annotation.is_none() {
+                    return Err(invalid(
+                        "--field needs a --dialect to read it from the header, or an --annotation"
+                            .to_string(),
+                    ));
+                }
+                if let Some(path) = annotation {
+                    let with_location = field == "location" || field == "chromosome";
+                    found.annotation = Some(Annotation::load(path, with_location)?);
+                }
+                Grouping::Field(field.clone(), found)
+            }
+            (_, _, Some(path)) => Grouping::Map(read_map(path)?),
+            _ => {
+                return Err(invalid(
+                    "Pick how records are grouped with --regex, --field or --map".to_string(),
+                ))
+            }
+        };
+
+        println!("Fasta file for processing: {}", fasta_file);
+        fs::create_dir_all(outpath)?;
+        let mut writer = GroupWriter {
+            outdir: PathBuf::from(outpath),
+            max_open: *max_open,
+            force: *force,
+            groups: BTreeMap::new(),
+            names: HashSet::new(),
+            open: HashMap::new(),
+            tick: 0,
+            reopened: 0,
+        };
+        let mut reader = open_fasta(fasta_file)?;
+        let mut ungrouped_records = 0;
+        let read = reader.records().try_for_each(|result| {
+            let record = result?;
+            let group = match grouping.group(&record) {
+                Some(group) if !group.is_empty() => group,
+                _ => {
+                    ungrouped_records += 1;
+                    ungrouped.to_string()
+                }
+            };
+            writer.write(&group, &record, |group| {
+                name_for(group).expect("checked before the input is read")
+            })
+        });
+        if let Err(e) = read {
+            writer.discard();
+            return Err(e);
+        }
+        let reopened = writer.reopened;
+        let groups = writer.finish()?;
+
+        if let Grouping::Field(_, dialect) = &grouping {
+            if let Some(annotation) = &dialect.annotation {
+                annotation.report();
+            }
+        }
+        if ungrouped_records > 0 {
+            println!(
+                "{} {} records had no group and went to {}",
+                "WARNING:".yellow(),
+                ungrouped_records,
+                ungrouped
+            );
+        }
+        if reopened > 0 {
+            println!(
+                "More groups than --max-open {}, files were closed and reopened {} times",
+                max_open, reopened
+            );
+        }
+
+        // One row per group, also printed
+        let summary_file = Path::new(outpath).join(format!("{}_groups.tsv", accession));
+        let mut summary = BufWriter::new(File::create(&summary_file)?);
+        writeln!(summary, "group\tfile\trecords\tresidues")?;
+        println!("Wrote {} groups", groups.len());
+        println!("Group\tFile\tRecords\tResidues");
+        for (group, entry) in &groups {
+            let row = format!(
+                "{}\t{}\t{}\t{}",
+                group, entry.file_name, entry.records, entry.residues
+            );
+            writeln!(summary, "{}", row)?;
+            println!("{}", row);
+        }
+        summary.flush()?;
+        println!("Group summary written to: {}", summary_file.display());
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::generics::scratch::Scratch;
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Command {
+            #[command(flatten)]
+            options: GroupOptions,
+        }
+
+        fn split(dir: &Path, fasta: &str, args: &[&str]) -> io::Result<()> {
+            let input = dir.join("input.fa");
+            fs::write(&input, fasta).unwrap();
+            let output = dir.join("groups");
+            let mut argv = vec![
+                "splitgroup",
+                "-f",
+                input.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+                "--accession",
+                "sp",
+            ];
+            argv.extend(args);
+            split_file_by_group(&Command::parse_from(argv).options)
+        }
+
+        fn files(dir: &Path) -> Vec<String> {
+            let mut names: Vec<String> = fs::read_dir(dir.join("groups"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        }
+
+        fn ids(path: &Path) -> Vec<String> {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .filter_map(|line| line.strip_prefix('>'))
+                .map(str::to_string)
+                .collect()
+        }
+
+        #[test]
+        fn groups_made_the_same_by_safe_name_get_their_own_files() {
+            let dir = Scratch::new("group-safe");
+            let fasta = ">1 a/b\nMKV\n>2 a_b\nMKV\n>3 a/b\nMKV\n>4 .hidden\nMKV\n";
+            split(&dir, fasta, &["--regex", r" (?P<group>\S+)"]).unwrap();
+            assert_eq!(
+                files(&dir),
+                ["sp__hidden.fa", "sp_a_b.fa", "sp_a_b_2.fa", "sp_groups.tsv"]
+            );
+            assert_eq!(ids(&dir.join("groups/sp_a_b.fa")), ["1 a/b", "3 a/b"]);
+            assert_eq!(ids(&dir.join("groups/sp_a_b_2.fa")), ["2 a_b"]);
+        }
+
+        #[test]
+        fn closed_files_are_reopened_and_appended_to_in_order() {
+            let dir = Scratch::new("group-reopen");
+            let mut fasta = String::new();
+            for index in 0..12 {
+                fasta.push_str(&format!(">r{} g{}\nMKV\n", index, index % 3));
+            }
+            split(
+                &dir,
+                &fasta,
+                &["--regex", r" (?P<group>g\d)", "--max-open", "1"],
+            )
+            .unwrap();
+            for group in 0..3 {
+                let expected: Vec<String> = (0..12)
+                    .filter(|index| index % 3 == group)
+                    .map(|index| format!("r{} g{}", index, group))
+                    .collect();
+                assert_eq!(ids(&dir.join(format!("groups/sp_g{}.fa", group))), expected);
+            }
+        }
+
+        #[test]
+        fn map_ids_fall_back_to_no_version() {
+            let dir = Scratch::new("group-map");
+            let map = dir.join("map.tsv");
+            fs::write(&map, "# id\tgroup\nENSP1\tchr1\n\nENSP2.3\tchr2\n").unwrap();
+            let fasta = ">ENSP1.4\nMKV\n>ENSP2.3\nMKV\n>ENSP2.4\nMKV\n>XP_9\nMKV\n";
+            split(&dir, fasta, &["--map", map.to_str().unwrap()]).unwrap();
+            assert_eq!(ids(&dir.join("groups/sp_chr1.fa")), ["ENSP1.4"]);
+            assert_eq!(ids(&dir.join("groups/sp_chr2.fa")), ["ENSP2.3"]);
+            // Only the record's version comes off, not the map's
+            assert_eq!(
+                ids(&dir.join("groups/sp_ungrouped.fa")),
+                ["ENSP2.4", "XP_9"]
+            );
+
+            fs::write(&map, "ENSP1 chr1\n").unwrap();
+            let error = read_map(map.to_str().unwrap()).unwrap_err();
+            assert!(error.to_string().contains("line 1"));
+        }
+
+        #[test]
+        fn records_without_a_group_share_the_ungrouped_file() {
+            let dir = Scratch::new("group-ungrouped");
+            let fasta = ">1 chr=1\nMKV\n>2\nMKV\n>3 chr=\nMKV\n>4 chr=1\nMKV\n";
+            split(
+                &dir,
+                fasta,
+                &["--regex", r"chr=(?P<group>\w*)", "--ungrouped", "none"],
+            )
+            .unwrap();
+            assert_eq!(files(&dir), ["sp_1.fa", "sp_groups.tsv", "sp_none.fa"]);
+            assert_eq!(ids(&dir.join("groups/sp_none.fa")), ["2", "3 chr="]);
+            let summary = fs::read_to_string(dir.join("groups/sp_groups.tsv")).unwrap();
+            assert!(summary.contains("none\tsp_none.fa\t2\t6\n"));
+        }
+
+        #[test]
+        fn an_error_part_way_leaves_no_temp_files() {
+            let dir = Scratch::new("group-error");
+            fs::create_dir_all(dir.join("groups")).unwrap();
+            fs::write(dir.join("groups/sp_b.fa"), ">old\nMKV\n").unwrap();
+            let fasta = ">1 a\nMKV\n>2 b\nMKV\n";
+            let error = split(&dir, fasta, &["--regex", r" (?P<group>\w)"]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+            assert_eq!(files(&dir), ["sp_b.fa"]);
+        }
+
+        #[test]
+        fn a_stale_temp_file_needs_force() {
+            let dir = Scratch::new("group-stale");
+            fs::create_dir_all(dir.join("groups")).unwrap();
+            fs::write(dir.join("groups/.sp_a.fa.tmp"), ">old\n").unwrap();
+            let fasta = ">1 a\nMKV\n";
+            let error = split(&dir, fasta, &["--regex", r" (?P<group>\w)"]).unwrap_err();
+            assert!(error.to_string().contains(".sp_a.fa.tmp already exists"));
+
+            split(&dir, fasta, &["--regex", r" (?P<group>\w)", "--force"]).unwrap();
+            assert_eq!(files(&dir), ["sp_a.fa", "sp_groups.tsv"]);
+            assert_eq!(ids(&dir.join("groups/sp_a.fa")), ["1 a"]);
+        }
+    }
+}
@@ -1,137 +1,379 @@
 pub mod split_by_size_mod {
-    use crate::cli::{DType, OriginType};
-    use crate::generics::{only_keys, validate_fasta, write_fasta};
-    use noodles::fasta;
-    use noodles::fasta::record::Definition;
-    use noodles::fasta::repository::adapters::IndexedReader;
+    use crate::cli::{OriginType, SplitOptions, SplitStrategy};
+    use crate::generics::dedupe::{dedupe, dedupe_table_path};
+    use crate::generics::detect::detect_types;
+    use crate::generics::dialect::load_sanitiser;
+    use crate::generics::isoform::{kept_records, longest_isoforms};
+    use crate::generics::layout::{output_name, Layout, SIZE_FILE_NAME};
+    use crate::generics::manifest::{manifest_path, Manifest, Resume};
+    use crate::generics::pipeline::ChunkWriter;
+    use crate::generics::qc::{qc_filter, qc_report_path};
+    use crate::generics::rename::rename_map_path;
+    use crate::generics::translate::{load_translator, peptide_sizes, translated, Translator};
+    use crate::generics::{
+        data_type_folder, index_sizes, open_fasta, open_indexed_fasta, validate_fasta,
+    };
     use noodles::fasta::Record;
-    use std::collections::HashMap;
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
     use std::io;
-    use std::path::Path;
-
-    pub fn find_chunks<'a>(
-        header_sizes: &'a HashMap<std::string::String, usize>,
-        size: &usize,
-    ) -> HashMap<usize, HashMap<&'a String, &'a usize>> {
-        //let mut new_map = HashMap::new();
-        let mut chunk = 1;
-        let mut new_map: HashMap<usize, HashMap<&String, &usize>> = HashMap::new();
-        let mut subset_map: HashMap<&String, &usize> = HashMap::new();
-        let mut temp_map: HashMap<&String, &usize> = HashMap::new();
-
-        for i in header_sizes {
-            let scaff_name = i.0;
-            let scaff_size = i.1;
-            // If scaffold size is greater than chunk then output
-            // straight away
-            if i.1 > size {
-                // Must be something cleaner for this bit
-                temp_map.insert(scaff_name, scaff_size);
-                new_map.insert(chunk, temp_map);
-
-                // Clear Hashmap
-                temp_map = HashMap::new();
-                chunk += 1;
-            // If Scaffold not > chunk size, add to HashMap
-            // scan through HashMap and check whether greater than Chunk.
+    use std::path::{Path, PathBuf};
+
+    pub fn first_fit_decreasing(header_sizes: &[(String, usize)], size: &usize) -> Vec<Vec<usize>> {
+        // Largest records first, each one goes into the first bin
+        // with enough room left for it, otherwise a new bin is made.
+        // A record larger than size will always end up on its own.
+        // Returns bins of indexes into header_sizes
+        let mut order: Vec<usize> = (0..header_sizes.len()).collect();
+        order.sort_by(|a, b| header_sizes[*b].1.cmp(&header_sizes[*a].1).then(a.cmp(b)));
+
+        let mut bins: Vec<Vec<usize>> = Vec::new();
+        let mut totals: Vec<usize> = Vec::new();
+        for index in order {
+            let record_size = header_sizes[index].1;
+            match totals.iter().position(|total| total + record_size <= *size) {
+                Some(bin) => {
+                    bins[bin].push(index);
+                    totals[bin] += record_size;
+                }
+                None => {
+                    bins.push(vec![index]);
+                    totals.push(record_size);
+                }
+            }
+        }
+        bins
+    }
+
+    pub fn equal_bins(header_sizes: &[(String, usize)], bin_count: &usize) -> Vec<Vec<usize>> {
+        // Largest records first, each one goes into whichever of the
+        // bin_count bins currently holds the least sequence.
+        // Ties go to the lowest numbered bin so the result is repeatable
+        let mut order: Vec<usize> = (0..header_sizes.len()).collect();
+        order.sort_by(|a, b| header_sizes[*b].1.cmp(&header_sizes[*a].1).then(a.cmp(b)));
+
+        let mut bins: Vec<Vec<usize>> = vec![Vec::new(); *bin_count];
+        let mut totals: BinaryHeap<Reverse<(usize, usize)>> =
+            (0..*bin_count).map(|bin| Reverse((0, bin))).collect();
+        for index in order {
+            let Reverse((total, bin)) = totals.pop().unwrap();
+            bins[bin].push(index);
+            totals.push(Reverse((total + header_sizes[index].1, bin)));
+        }
+        bins
+    }
+
+    fn order_bins(mut bins: Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+        // Keep records in input order inside a bin, and number the bins
+        // by the first record they hold, empty bins are dropped
+        bins.retain(|bin| !bin.is_empty());
+        for bin in bins.iter_mut() {
+            bin.sort_unstable();
+        }
+        bins.sort_by_key(|bin| bin[0]);
+        bins
+    }
+
+    fn report_packing(bins: &[Vec<usize>], header_sizes: &[(String, usize)]) {
+        let totals: Vec<usize> = bins
+            .iter()
+            .map(|bin| bin.iter().map(|index| header_sizes[*index].1).sum())
+            .collect();
+        let mean = totals.iter().sum::<usize>() as f64 / totals.len().max(1) as f64;
+
+        println!("Packed into {} files (mean size {:.0})", bins.len(), mean);
+        println!("File\tRecords\tSize\tImbalance");
+        for (file_counter, (bin, total)) in bins.iter().zip(&totals).enumerate() {
+            let imbalance = if mean > 0.0 {
+                (*total as f64 - mean) / mean * 100.0
             } else {
-                subset_map.insert(scaff_name, scaff_size);
-                // If this list sums to larger than Chunk then
-                // remove last item and check again.
-                // if removing [-1] makes total size < chunk
-                // out to file and keep that [-1] in list for next round of
-                // chunking
-                if subset_map.len() > 1 {
-                    let summed: usize = subset_map.values().copied().sum();
-                    if summed > *size {
-                        subset_map.remove(scaff_name);
-                        let summed: usize = subset_map.values().copied().sum();
-                        if summed < *size {
-                            new_map.insert(chunk, subset_map);
-                            chunk += 1;
-                        } else {
-                            println!("ERROR: MORE LOGIC NEEDED TO SPLIT UP")
-                        }
-                        subset_map = HashMap::new();
-                        subset_map.insert(scaff_name, scaff_size);
-                    }
+                0.0
+            };
+            println!(
+                "{}\t{}\t{}\t{:+.1}%",
+                file_counter + 1,
+                bin.len(),
+                total,
+                imbalance
+            );
+        }
+    }
+
+    fn write_packed(
+        bins: &[Vec<usize>],
+        writer: &ChunkWriter,
+        layout: &Layout,
+        keep: &Option<Vec<bool>>,
+        translator: &Option<Translator>,
+        manifest: &mut Manifest,
+        manifest_file: &Path,
+    ) -> io::Result<()> {
+        // Second pass over the input, each record goes straight into the
+        // temp file of its bin, so nothing is held whatever the packing.
+        // Any bin already in the manifest is left as it is (--resume)
+        let chunk_paths: Vec<PathBuf> = (0..bins.len())
+            .map(|bin| Path::new(writer.outdir).join(layout.file_name(bin + 1, bins[bin].len())))
+            .collect();
+        let mut done = vec![false; bins.len()];
+        for chunk in &manifest.chunks {
+            let bin = chunk_paths
+                .iter()
+                .position(|path| path.to_string_lossy() == chunk.path);
+            match bin {
+                Some(bin) if bins[bin].len() == chunk.records => done[bin] = true,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{} doesn't match the current packing, the input or settings have changed so this run can't be resumed",
+                            chunk.path
+                        ),
+                    ))
                 }
             }
         }
-        new_map.insert(chunk.to_owned(), subset_map.to_owned());
 
-        new_map
+        let mut bin_of = vec![0; bins.iter().map(|bin| bin.len()).sum()];
+        for (bin, members) in bins.iter().enumerate() {
+            for index in members {
+                bin_of[*index] = bin;
+            }
+        }
+        let file_names = (0..bins.len())
+            .map(|bin| (!done[bin]).then(|| layout.file_name(bin + 1, bins[bin].len())))
+            .collect();
+
+        let mut reader = open_fasta(&writer.options.fasta_file)?;
+        writer.run_files(manifest, manifest_file, file_names, |files| {
+            let mut records_read = 0;
+            for result in translated(kept_records(reader.records(), keep), translator) {
+                let Some(&bin) = bin_of.get(records_read) else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "more records were read than were packed, is the .fai out of date?",
+                    ));
+                };
+                files.write(bin, result?)?;
+                records_read += 1;
+            }
+            if records_read < bin_of.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} records were packed but {} were read, is the .fai out of date?",
+                        bin_of.len(),
+                        records_read
+                    ),
+                ));
+            }
+            Ok(())
+        })?;
+
+        // Bins kept by --resume come first, list them all by file number
+        manifest.chunks.sort_by_key(|chunk| {
+            chunk_paths
+                .iter()
+                .position(|path| path.to_string_lossy() == chunk.path)
+        });
+        Ok(())
     }
 
-    #[allow(unused_variables)]
     pub fn split_file_by_size(
-        fasta_file: &String,
         chunk_size: &usize,
-        data_type: &DType,
-        origin: &OriginType,
-        sanitise: &bool,
-        outpath: &String,
+        strategy: &SplitStrategy,
+        bins: &Option<usize>,
+        options: &SplitOptions,
     ) -> io::Result<()> {
-        // Abstract this out to generics
-        let data_type = match data_type {
-            DType::Pep => "pep",
-            DType::Cdna => "cdna",
-            DType::Cds => "cds",
-            DType::Rna => "rna",
-            DType::Other => {
-                panic!("NOT PLANNED FOR")
-            }
+        // Fill in or check -d and -r from a sample of the input
+        let options = &detect_types(options)?;
+        let fasta_file = &options.fasta_file;
+
+        // pep, cdna, rna, cds or the --label of -d other
+        let data_type_name = data_type_folder(options)?;
+        // --translate writes the peptides into the pep tree
+        let translator = load_translator(options)?;
+        let data_type = if translator.is_some() {
+            "pep"
+        } else {
+            &data_type_name
         };
 
-        let origin_db = match origin {
+        let origin_db = match options.origin_db() {
             OriginType::Other => "na",
             OriginType::Ensembl => "ensembl",
-            OriginType::Ncbi => "NCBI",
+            OriginType::Ncbi => "ncbi",
         };
 
-        let path_obj = Path::new(fasta_file);
-        let grab_name = path_obj.file_name().unwrap();
-        let actual_list: Vec<&str> = grab_name.to_str().unwrap().split('.').collect();
-        let actual_name = actual_list[0];
+        // --accession, or the input file name without its suffixes
+        let actual_name = &output_name(options);
 
-        let new_outpath = format!("{}/{}/{}/", outpath, actual_name, data_type);
+        let layout = Layout::new(options, actual_name, data_type, *chunk_size, SIZE_FILE_NAME)?;
+        let manifest_file = manifest_path(&layout, &options.manifest_format);
+        let manifest_file = Path::new(&manifest_file);
 
         println!("Fasta file for processing: {:?}", &fasta_file);
         println!("Size to chunk fasta into: {:?}", &chunk_size);
         println!("Data is from: {:?}", &origin_db);
+        println!("Packing strategy: {:?}", &strategy);
 
-        let validation = validate_fasta(fasta_file);
-
-        // Deserved better error handling here
-        let results = validation.unwrap();
+        let settings = format!(
+            "sizesplit chunk_size={} strategy={:?} bins={:?} {}",
+            chunk_size,
+            strategy,
+            bins,
+            options.settings()
+        );
+        let resume = if options.resume {
+            Resume::load(manifest_file, &options.manifest_format, &settings)?
+        } else {
+            Resume::default()
+        };
+        let mut manifest = Manifest::new(
+            options.command_line.clone(),
+            settings,
+            resume.chunks.clone(),
+        );
 
-        // Returns only the HashMap< usize, Hashmap<String, usize>>
-        let split_hash = find_chunks(&results, chunk_size);
+        // Headers are sanitised and chunks written by the ChunkWriter threads
+        let dialect = load_sanitiser(options)?;
+        let rename_file = rename_map_path(&layout);
+        let writer = ChunkWriter {
+            outdir: &layout.outdir,
+            dialect: dialect.as_ref(),
+            rename_file: Path::new(&rename_file),
+            options,
+        };
 
-        let reader = fasta::indexed_reader::Builder::default().build_from_path(fasta_file)?;
-        let adapter = IndexedReader::new(reader);
-        let fasta_repo = fasta::Repository::new(adapter);
+        // Which records survive QC, --longest-isoform and --dedupe, each needs a pass of its own
+        let qc_file = qc_report_path(&layout);
+        let qc = qc_filter(options, &manifest.settings, Path::new(&qc_file))?;
+        let keep = if options.longest_isoform {
+            Some(longest_isoforms(options, &qc)?)
+        } else {
+            qc
+        };
+        let dedupe_file = dedupe_table_path(&layout);
+        let keep = dedupe(options, keep, Path::new(&dedupe_file))?;
 
-        for i in split_hash {
-            let mut record_list: Vec<Record> = Vec::new();
-            let list: Vec<&String> = only_keys(i.1.to_owned()).collect();
-            for ii in list {
-                let results = fasta_repo.get(ii.as_bytes()).transpose();
-                let new_rec = match results {
-                    Ok(data) => {
-                        // this is where sanitation would happen
-                        let definition = Definition::new(ii.as_bytes(), None);
-                        Record::new(definition, data.unwrap())
+        if *strategy != SplitStrategy::Stream {
+            // Packing needs every length up front, from the .fai if there
+            // is an up to date one, otherwise from a pass of its own.
+            // Translated records are packed by their peptide length
+            let mut header_sizes = match &translator {
+                Some(translator) => peptide_sizes(fasta_file, translator.table)?,
+                None => match open_indexed_fasta(fasta_file)? {
+                    Some(reader) => {
+                        println!("Record lengths from {}.fai", fasta_file);
+                        index_sizes(&reader)
                     }
-                    Err(e) => panic!("{:?}", e),
-                };
-                record_list.push(new_rec)
+                    None => {
+                        validate_fasta(fasta_file).map_err(|e| io::Error::other(e.to_string()))?
+                    }
+                },
+            };
+            if let Some(keep) = &keep {
+                let mut kept = keep.iter();
+                header_sizes.retain(|_| *kept.next().unwrap_or(&false));
             }
-            let file_name = format!("{}_f{}_{}.fasta", actual_name, i.0, data_type);
 
-            let _ = write_fasta(&new_outpath, file_name, record_list);
+            let packed = match strategy {
+                SplitStrategy::FirstFitDecreasing => {
+                    first_fit_decreasing(&header_sizes, chunk_size)
+                }
+                _ => match bins {
+                    Some(bin_count) if *bin_count > 0 => equal_bins(&header_sizes, bin_count),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "--strategy equal-bins needs --bins set to 1 or more",
+                        ))
+                    }
+                },
+            };
+            let packed = order_bins(packed);
+
+            report_packing(&packed, &header_sizes);
+            write_packed(
+                &packed,
+                &writer,
+                &layout,
+                &keep,
+                &translator,
+                &mut manifest,
+                manifest_file,
+            )?;
+
+            // Equal bins ignore --memory_size, so nothing is oversize there
+            let size_limit = match strategy {
+                SplitStrategy::FirstFitDecreasing => Some(*chunk_size),
+                _ => None,
+            };
+            if let Some(translator) = &translator {
+                translator.report();
+            }
+            return writer.finish(&manifest, manifest_file, size_limit);
         }
 
-        Ok(())
+        // Single pass over the input, no index or size map needed.
+        // Records are held until adding the next one would take the
+        // chunk over budget, then the chunk is written out and the
+        // next one started. So memory never goes much past chunk_size
+        // and the input order is kept.
+        // A record larger than the budget gets a chunk to itself.
+        let mut reader = open_fasta(fasta_file)?;
+
+        let mut record_list: Vec<Record> = Vec::new();
+        let mut counter: usize = 0;
+        let mut file_counter: usize = resume.chunks.len() + 1;
+        let mut sequence_size: usize = 0;
+
+        writer.run(&mut manifest, manifest_file, |chunks| {
+            for (index, result) in
+                translated(kept_records(reader.records(), &keep), &translator).enumerate()
+            {
+                let record = result?;
+                let record_size = record.sequence().len();
+                sequence_size += record_size;
+
+                // Already written by the run being resumed
+                if index < resume.records() {
+                    if resume.is_boundary(index) {
+                        resume.check(index, &record, dialect.as_ref())?;
+                    }
+                    continue;
+                }
+
+                if !record_list.is_empty() && counter + record_size > *chunk_size {
+                    chunks.send(
+                        layout.file_name(file_counter, record_list.len()),
+                        record_list,
+                    )?;
+                    file_counter += 1;
+                    counter = 0;
+                    record_list = Vec::new();
+                }
+
+                counter += record_size;
+                record_list.push(record);
+            }
+
+            if !record_list.is_empty() {
+                chunks.send(
+                    layout.file_name(file_counter, record_list.len()),
+                    record_list,
+                )?;
+            }
+            Ok(())
+        })?;
+
+        println!(
+            "Streamed {} residues into {} files",
+            sequence_size,
+            manifest.chunks.len()
+        );
+        if let Some(translator) = &translator {
+            translator.report();
+        }
+        writer.finish(&manifest, manifest_file, Some(*chunk_size))
     }
 }
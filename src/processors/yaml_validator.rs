@@ -67,7 +67,7 @@ pub mod yaml_validator_mod {
 
     // I assume this is because it exists in a newer version of rust, than I have locally to test
     #[allow(unknown_lints)]
-    #[allow(elided_named_lifetimes)]
+    #[allow(mismatched_lifetime_syntaxes)]
     impl<'a> YamlResults<'a> {
         fn is_cram_valid(&self) -> String {
             // this should add a field to the cram_results struct
@@ -0,0 +1,230 @@
+use scratch::Scratch;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[path = "../src/generics/scratch.rs"]
+mod scratch;
+
+fn write_input(dir: &Path) -> PathBuf {
+    // Lots of equal lengths so any tie breaking has to be stable
+    let mut fasta = String::new();
+    for i in 0..40 {
+        let length = 20 + (i * 37 % 11) * 15;
+        fasta.push_str(&format!(">seq{:02} some description\n", i));
+        fasta.push_str(&"ACDEFGHIKLMNPQRSTVWY".repeat(length / 20 + 1)[..length]);
+        fasta.push('\n');
+    }
+    let path = dir.join("species.pep.fa");
+    fs::write(&path, fasta).unwrap();
+    path
+}
+
+fn run_split(input: &Path, outdir: &Path, args: &[&str]) -> Vec<(String, Vec<u8>)> {
+    let status = Command::new(env!("CARGO_BIN_EXE_treeval_utils"))
+        .args(args)
+        .arg("-f")
+        .arg(input)
+        .args(["-d", "pep", "-r", "ensembl", "-o"])
+        .arg(outdir)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "{:?} failed", args);
+
+    let mut files: Vec<(String, Vec<u8>)> = fs::read_dir(outdir.join("species/pep"))
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            (name, fs::read(path).unwrap())
+        })
+        .collect();
+    // Shortest first so f10 sorts after f9
+    files.sort_by(|a, b| (a.0.len(), &a.0).cmp(&(b.0.len(), &b.0)));
+    files
+}
+
+fn record_names(files: &[(String, Vec<u8>)]) -> Vec<Vec<String>> {
+    files
+        .iter()
+        .map(|(_, data)| {
+            String::from_utf8_lossy(data)
+                .lines()
+                .filter_map(|line| line.strip_prefix('>'))
+                .map(|header| header.split(' ').next().unwrap().to_string())
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn split_modes_are_byte_identical_across_runs() {
+    let modes: Vec<Vec<&str>> = vec![
+        vec!["splitcount", "-s", "7"],
+        vec!["splitcount", "-s", "3", "-t", "4", "--in-flight", "1"],
+        vec!["sizesplit", "-s", "500"],
+        vec![
+            "sizesplit",
+            "-s",
+            "500",
+            "--strategy",
+            "first-fit-decreasing",
+        ],
+        vec!["sizesplit", "--strategy", "equal-bins", "-n", "6"],
+    ];
+
+    for (index, args) in modes.iter().enumerate() {
+        let dir = Scratch::new(&format!("determinism{}", index));
+        let input = write_input(&dir);
+
+        let first = run_split(&input, &dir.join("run1"), args);
+        let second = run_split(&input, &dir.join("run2"), args);
+        assert!(!first.is_empty());
+        assert_eq!(first, second, "{:?} output changed between runs", args);
+
+        // Records keep their input order inside and across files for the
+        // streaming modes, and inside each file for the packing modes
+        let names = record_names(&first);
+        let mut expected: Vec<String> = names.concat();
+        expected.sort();
+        if args.contains(&"--strategy") {
+            for file in &names {
+                assert!(file.windows(2).all(|pair| pair[0] < pair[1]));
+            }
+            let firsts: Vec<&String> = names.iter().map(|file| &file[0]).collect();
+            assert!(firsts.windows(2).all(|pair| pair[0] < pair[1]));
+        } else {
+            assert_eq!(names.concat(), expected);
+        }
+        assert_eq!(expected.len(), 40);
+    }
+}
+
+#[test]
+fn header_collisions_resolve_the_same_across_threads() {
+    // Four genes over and over, so -c gives the same header again and
+    // again and the _N suffixes depend on which record got there first
+    let dir = Scratch::new("determinism-collisions");
+    let mut fasta = String::new();
+    for i in 0..60 {
+        let id = format!("{:011}.1", i % 4);
+        fasta.push_str(&format!(
+            ">ENSP{} pep chromosome:GRCh38:1:1:100:1 gene:ENSG{} transcript:ENST{} gene_biotype:protein_coding\n",
+            id, id, id
+        ));
+        fasta.push_str(&"MKVLAAG".repeat(i % 5 + 1));
+        fasta.push('\n');
+    }
+    let input = dir.join("species.pep.fa");
+    fs::write(&input, fasta).unwrap();
+
+    let run = |name: &str, threads: &str| {
+        let outdir = dir.join(name);
+        let args = ["splitcount", "-c", "-s", "4", "-t", threads];
+        let files = run_split(&input, &outdir, &args);
+        // chunk_file paths start with each run's own -o
+        let rename_map = fs::read_to_string(outdir.join("species/pep_rename_map.tsv"))
+            .unwrap()
+            .replace(outdir.to_str().unwrap(), "OUT");
+        (files, rename_map)
+    };
+    let (single, single_map) = run("threads1", "1");
+    let (threaded, threaded_map) = run("threads4", "4");
+    assert_eq!(single, threaded);
+    assert_eq!(single_map, threaded_map);
+    assert!(single_map.contains("duplicate"));
+    assert!(single_map.contains("_15\t"));
+}
+
+#[test]
+fn threads_and_open_files_do_not_change_the_output() {
+    // Each mode against a single thread and the default --max-open
+    let modes: Vec<(Vec<&str>, Vec<&str>)> = vec![
+        (
+            vec!["splitcount", "-s", "3", "-t", "1"],
+            vec!["splitcount", "-s", "3", "-t", "4", "--in-flight", "2"],
+        ),
+        (
+            vec!["sizesplit", "-s", "500", "-t", "1"],
+            vec!["sizesplit", "-s", "500", "-t", "4"],
+        ),
+        (
+            vec!["sizesplit", "--strategy", "equal-bins", "-n", "6"],
+            vec![
+                "sizesplit",
+                "--strategy",
+                "equal-bins",
+                "-n",
+                "6",
+                "-t",
+                "4",
+                "--max-open",
+                "2",
+            ],
+        ),
+        (
+            vec!["splitcount", "-n", "5", "--n-files-mode", "round-robin"],
+            vec![
+                "splitcount",
+                "-n",
+                "5",
+                "--n-files-mode",
+                "round-robin",
+                "--max-open",
+                "1",
+            ],
+        ),
+    ];
+
+    for (index, (single, threaded)) in modes.iter().enumerate() {
+        let dir = Scratch::new(&format!("determinism-threads{}", index));
+        let input = write_input(&dir);
+        let expected = run_split(&input, &dir.join("single"), single);
+        let output = run_split(&input, &dir.join("threaded"), threaded);
+        assert!(expected.len() > 1);
+        assert_eq!(expected, output, "{:?} differs from {:?}", threaded, single);
+    }
+}
+
+#[test]
+fn dry_run_plans_match_real_runs() {
+    let modes: Vec<Vec<&str>> = vec![
+        vec!["splitcount", "-s", "7"],
+        vec!["sizesplit", "-s", "500"],
+        vec!["sizesplit", "--strategy", "equal-bins", "-n", "6"],
+    ];
+
+    for (index, args) in modes.iter().enumerate() {
+        let dir = Scratch::new(&format!("determinism-dry-run{}", index));
+        let input = write_input(&dir);
+        let planned = dir.join("planned");
+        let plan_json = dir.join("plan.json");
+        let output = Command::new(env!("CARGO_BIN_EXE_treeval_utils"))
+            .args(args)
+            .arg("-f")
+            .arg(&input)
+            .args(["-d", "pep", "-r", "ensembl", "--dry-run", "--plan-json"])
+            .arg(&plan_json)
+            .arg("-o")
+            .arg(&planned)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?} --dry-run failed", args);
+        assert!(!planned.exists(), "{:?} --dry-run wrote under -o", args);
+
+        let plan: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&plan_json).unwrap()).unwrap();
+        let chunks = plan["chunks"].as_array().unwrap();
+        let written = run_split(&input, &dir.join("real"), args);
+        assert_eq!(plan["files"], written.len(), "{:?}", args);
+        assert_eq!(chunks.len(), written.len(), "{:?}", args);
+        // The same files, with the same records in each
+        for (chunk, (name, data)) in chunks.iter().zip(&written) {
+            assert!(chunk["path"].as_str().unwrap().ends_with(name.as_str()));
+            let records = data.iter().filter(|byte| **byte == b'>').count();
+            assert_eq!(chunk["records"], records);
+            assert_eq!(chunk["bytes"], data.len());
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use scratch::Scratch;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[path = "../src/generics/scratch.rs"]
+mod scratch;
+
+fn treeval_utils(args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_treeval_utils"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed\n{}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn write_input(path: &Path) {
+    let mut fasta = String::new();
+    for i in 0..10 {
+        fasta.push_str(&format!(">seq{:02}\nACDEFGHIKLMNPQRSTVWY\n", i));
+    }
+    fs::write(path, fasta).unwrap();
+}
+
+#[test]
+fn gencsv_catalogues_a_split_with_the_same_layout() {
+    // {type} isn't the last folder, so gencsv has to go by the value it
+    // reads back out of the path rather than the folder's own name
+    let dir = Scratch::new("gencsv-layout");
+    let input = dir.join("species.pep.fa");
+    write_input(&input);
+    let outpath = dir.join("geneset");
+    let (input, outpath) = (input.to_str().unwrap(), outpath.to_str().unwrap());
+    let layout = "{type}/{accession}";
+    for args in [vec!["-d", "pep"], vec!["-d", "other", "--label", "domains"]] {
+        let mut split = vec!["splitcount", "-f", input, "-r", "ensembl", "-s", "4"];
+        split.extend(["-o", outpath, "--layout", layout, "--accession", "GCA_1.1"]);
+        split.extend(args);
+        treeval_utils(&split);
+    }
+    // Data put in place by hand has no manifest beside it
+    fs::create_dir_all(dir.join("geneset/cdna/GCA_1.1")).unwrap();
+    write_input(&dir.join("geneset/cdna/GCA_1.1/by_hand.fa"));
+    treeval_utils(&["generate_csv", "-i", outpath, "--layout", layout]);
+
+    let csv = fs::read_to_string(dir.join("geneset/csv_data/GCA_1.1-data.csv")).unwrap();
+    let mut rows: Vec<&str> = csv.lines().skip(1).collect();
+    rows.sort();
+    let mut expected = vec![format!("GCA_1.1,cdna,{}/cdna/GCA_1.1/by_hand.fa", outpath)];
+    for data_type in ["domains", "pep"] {
+        for index in 1..=3 {
+            expected.push(format!(
+                "GCA_1.1,{},{}/{}/GCA_1.1/GCA_1.1_f{}_c4.fa",
+                data_type, outpath, data_type, index
+            ));
+        }
+    }
+    assert_eq!(rows, expected);
+}